@@ -0,0 +1,35 @@
+/*
+ * Print the inputs of a rule for a few seeds:
+ *
+ *     cc -Icapi/include capi/examples/generate.c -Ltarget/release -lbnf -o generate
+ *     LD_LIBRARY_PATH=target/release ./generate JsonArray 10
+ */
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "bnf.h"
+
+int main(int argc, char **argv) {
+    const char *rule = argc > 1 ? argv[1] : NULL;
+    unsigned count = argc > 2 ? (unsigned)atoi(argv[2]) : 10;
+
+    struct grammar *grammar = grammar_new(rule);
+    if (!grammar) {
+        fprintf(stderr, "unknown rule %s\n", rule);
+        return 1;
+    }
+
+    uint8_t buf[4096];
+    for (uint64_t seed = 0; seed < count; seed++) {
+        size_t size = grammar_generate(grammar, seed, buf, sizeof buf);
+        if (size > sizeof buf) {
+            fprintf(stderr, "seed %llu: %zu bytes, cut short\n", (unsigned long long)seed, size);
+            size = sizeof buf;
+        }
+        fwrite(buf, 1, size, stdout);
+        putchar('\n');
+    }
+
+    grammar_free(grammar);
+    return 0;
+}
@@ -0,0 +1,41 @@
+/*
+ * C API of the bnf shared library, built with
+ *
+ *     cargo build --release
+ *
+ * and linked with -lbnf. Matches src/ffi.rs of the bnf crate.
+ */
+#ifndef BNF_H
+#define BNF_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Rule generating the inputs */
+struct grammar;
+
+/*
+ * Grammar generating inputs from the rule called `rule`, such as "Json" or
+ * "JsonArray", "Json" if NULL, or NULL if there is no such rule
+ */
+struct grammar *grammar_new(const char *rule);
+
+/*
+ * Generate the input for `seed`, copying up to `cap` bytes of it to `buf`
+ * and returning its full size, like snprintf. The input was cut short if
+ * that is more than `cap`. `buf` may be NULL if `cap` is 0.
+ */
+size_t grammar_generate(struct grammar *grammar, uint64_t seed, uint8_t *buf, size_t cap);
+
+/* Free `grammar`, which may be NULL */
+void grammar_free(struct grammar *grammar);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
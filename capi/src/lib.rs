@@ -0,0 +1,6 @@
+//! Shared library `libbnf.so`
+//!
+//! Exports the C API and the custom mutator hooks of the `bnf` crate, which
+//! live behind its `cdylib` feature. A separate package, as a `cdylib` crate
+//! type on `bnf` itself would have to link `std` even for `no_std` users.
+extern crate bnf;
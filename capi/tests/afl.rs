@@ -0,0 +1,127 @@
+//! Loads the shared library the way AFL++ does and drives its custom
+//! mutator hooks.
+
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::slice;
+
+use bnf::value::Value;
+
+mod common;
+
+#[link(name = "dl")]
+extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+const RTLD_NOW: c_int = 2;
+
+type Init = unsafe extern "C" fn(*mut c_void, u32) -> *mut c_void;
+type Fuzz =
+    unsafe extern "C" fn(*mut c_void, *mut u8, usize, *mut *mut u8, *mut u8, usize, usize) -> usize;
+type PostProcess = unsafe extern "C" fn(*mut c_void, *mut u8, usize, *mut *mut u8) -> usize;
+type InitTrim = unsafe extern "C" fn(*mut c_void, *mut u8, usize) -> i32;
+type Trim = unsafe extern "C" fn(*mut c_void, *mut *mut u8) -> usize;
+type PostTrim = unsafe extern "C" fn(*mut c_void, u8) -> i32;
+type Deinit = unsafe extern "C" fn(*mut c_void);
+
+struct Library(*mut c_void);
+
+impl Library {
+    /// `libbnf.so`, freshly built
+    fn open() -> Library {
+        let path = common::library_dir().join("libbnf.so");
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        let handle = unsafe { dlopen(path.as_ptr(), RTLD_NOW) };
+        assert!(!handle.is_null(), "can't load {:?}", path);
+        Library(handle)
+    }
+
+    /// Symbol `name`, which must be of type `T`
+    unsafe fn get<T: Copy>(&self, name: &str) -> T {
+        let name = CString::new(name).unwrap();
+        let symbol = dlsym(self.0, name.as_ptr());
+        assert!(!symbol.is_null(), "missing symbol {:?}", name);
+        core::mem::transmute_copy(&symbol)
+    }
+}
+
+#[test]
+fn fuzz_and_post_process() {
+    let lib = Library::open();
+    unsafe {
+        let init: Init = lib.get("afl_custom_init");
+        let fuzz: Fuzz = lib.get("afl_custom_fuzz");
+        let post_process: PostProcess = lib.get("afl_custom_post_process");
+        let deinit: Deinit = lib.get("afl_custom_deinit");
+
+        let data = init(core::ptr::null_mut(), 42);
+        let mut input = br#"{"a":[1,2,{"b":null}],"c":"x"}"#.to_vec();
+        let mut other = br#"[true,false,[0]]"#.to_vec();
+        let mut out = core::ptr::null_mut();
+        for i in 0..1000 {
+            let add = match i % 2 {
+                0 => other.as_mut_ptr(),
+                _ => core::ptr::null_mut(),
+            };
+            let n = fuzz(
+                data,
+                input.as_mut_ptr(),
+                input.len(),
+                &mut out,
+                add,
+                other.len(),
+                64,
+            );
+            assert!(n <= 64);
+            let mutated = slice::from_raw_parts(out, n).to_vec();
+            assert!(
+                Value::parse(&mutated).is_ok(),
+                "{:?}",
+                String::from_utf8_lossy(&mutated)
+            );
+
+            let n = post_process(data, input.as_mut_ptr(), input.len(), &mut out);
+            assert_eq!(slice::from_raw_parts(out, n), &input[..]);
+            input = mutated;
+        }
+        deinit(data);
+    }
+}
+
+#[test]
+fn trim() {
+    let lib = Library::open();
+    unsafe {
+        let init: Init = lib.get("afl_custom_init");
+        let init_trim: InitTrim = lib.get("afl_custom_init_trim");
+        let trim: Trim = lib.get("afl_custom_trim");
+        let post_trim: PostTrim = lib.get("afl_custom_post_trim");
+        let deinit: Deinit = lib.get("afl_custom_deinit");
+
+        let data = init(core::ptr::null_mut(), 0);
+        assert_eq!(init_trim(data, b"nope".as_ptr() as *mut u8, 4), 0);
+
+        // Stands for the coverage: only the inputs with the needle are
+        // interesting
+        let mut input = br#"[1,{"a":[2,3],"b":["needle",4]},[5,[6]],7]"#.to_vec();
+        let steps = init_trim(data, input.as_mut_ptr(), input.len());
+        assert_eq!(steps, 13);
+        let mut step = 0;
+        let mut out = core::ptr::null_mut();
+        while step < steps {
+            let n = trim(data, &mut out);
+            let candidate = slice::from_raw_parts(out, n).to_vec();
+            assert!(Value::parse(&candidate).is_ok());
+            let success = candidate.windows(6).any(|w| w == b"needle");
+            if success {
+                input = candidate;
+            }
+            let next = post_trim(data, success as u8);
+            assert!(next >= step);
+            step = next;
+        }
+        assert_eq!(input, br#"[{"b":["needle"]}]"#);
+        deinit(data);
+    }
+}
@@ -0,0 +1,24 @@
+//! Shared by the tests loading the shared library
+
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Directory of `libbnf.so`, built first so the tests never load a stale
+/// one. `cargo test` only builds the rlib, the cdylib lands in the profile
+/// directory, next to `deps` where the test binary is.
+pub fn library_dir() -> &'static PathBuf {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let exe = std::env::current_exe().unwrap();
+        let dir = exe.parent().unwrap().parent().unwrap().to_path_buf();
+        let mut cargo = Command::new(env!("CARGO"));
+        cargo.args(["build", "-p", "bnf-capi"]);
+        if dir.ends_with("release") {
+            cargo.arg("--release");
+        }
+        let status = cargo.status().expect("can't run cargo");
+        assert!(status.success(), "building libbnf.so failed");
+        dir
+    })
+}
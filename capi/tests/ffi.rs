@@ -0,0 +1,138 @@
+//! Compiles the C example against `include/bnf.h` and the shared library,
+//! and checks that it generates what the Rust API does.
+
+use std::path::Path;
+use std::process::Command;
+use std::ptr;
+
+use bnf::ffi::{grammar_free, grammar_generate, grammar_new};
+use bnf::json4::JsonArray;
+use bnf::{Config, Generate, Rng};
+
+mod common;
+
+#[test]
+fn c_example() {
+    let lib = common::library_dir();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let example = lib.join("generate-c");
+    let status = Command::new("cc")
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-I"])
+        .arg(root.join("include"))
+        .arg(root.join("examples/generate.c"))
+        .arg("-L")
+        .arg(lib)
+        .args(["-lbnf", "-o"])
+        .arg(&example)
+        .status()
+        .expect("can't run cc");
+    assert!(status.success());
+
+    let output = Command::new(&example)
+        .args(["JsonArray", "100"])
+        .env("LD_LIBRARY_PATH", lib)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let mut expected = Vec::new();
+    for seed in 0..100 {
+        let mut buf = Vec::new();
+        JsonArray::generate(&mut Rng::seeded(seed), &Config::default(), &mut 0, &mut buf);
+        buf.truncate(4096);
+        expected.extend_from_slice(&buf);
+        expected.push(b'\n');
+    }
+    assert_eq!(output.stdout, expected);
+
+    let output = Command::new(&example)
+        .arg("Yaml")
+        .env("LD_LIBRARY_PATH", lib)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stderr, b"unknown rule Yaml\n");
+}
+
+#[test]
+fn size_query() {
+    unsafe {
+        let grammar = grammar_new(ptr::null());
+        for seed in 0..100 {
+            let size = grammar_generate(grammar, seed, ptr::null_mut(), 0);
+            let mut buf = vec![0; size];
+            assert_eq!(
+                grammar_generate(grammar, seed, buf.as_mut_ptr(), size),
+                size
+            );
+            let mut expected = Vec::new();
+            bnf::Generator::new().generate_seed(seed, &mut expected);
+            assert_eq!(buf, expected);
+        }
+        grammar_free(grammar);
+    }
+}
+
+/// C type of a Rust type used in the signatures of `src/ffi.rs`
+fn c_type(rust: &str) -> &'static str {
+    match rust {
+        "" => "void",
+        "*const c_char" => "const char *",
+        "*mut Grammar" => "struct grammar *",
+        "*mut u8" => "uint8_t *",
+        "u64" => "uint64_t",
+        "usize" => "size_t",
+        _ => panic!("no C type for {}", rust),
+    }
+}
+
+/// The header is written by hand, so check that it declares exactly the
+/// functions of `src/ffi.rs` with the same signatures
+#[test]
+fn header_matches_ffi() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let source = std::fs::read_to_string(root.join("../src/ffi.rs")).unwrap();
+    let mut expected: Vec<String> = source
+        .split("extern \"C\" fn ")
+        .skip(1)
+        .map(|item| {
+            let (name, rest) = item.split_once('(').unwrap();
+            let (params, rest) = rest.split_once(')').unwrap();
+            let ret = rest.split('{').next().unwrap().trim();
+            let params: Vec<String> = params
+                .split(',')
+                .filter(|param| !param.trim().is_empty())
+                .map(|param| {
+                    let (name, ty) = param.split_once(':').unwrap();
+                    format!("{}{}", c_type(ty.trim()), name.trim())
+                })
+                .collect();
+            let ret = c_type(ret.trim_start_matches("->").trim());
+            format!("{}{}({})", ret, name, params.join(","))
+        })
+        .collect();
+
+    // Declarations of the header without comments, preprocessor lines and
+    // `extern "C" {`
+    let header = std::fs::read_to_string(root.join("include/bnf.h")).unwrap();
+    let mut code = String::new();
+    for part in header.split("/*") {
+        code.push_str(part.split_once("*/").map_or(part, |(_, code)| code));
+    }
+    let mut declared: Vec<String> = code
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.contains('{') && !line.contains('}'))
+        .collect::<String>()
+        .split(';')
+        .filter(|declaration| declaration.contains('('))
+        .map(String::from)
+        .collect();
+
+    // Compare without whitespace, which the formatting of the two differs in
+    for list in [&mut expected, &mut declared] {
+        for declaration in list.iter_mut() {
+            declaration.retain(|c| !c.is_whitespace());
+        }
+        list.sort();
+    }
+    assert_eq!(declared, expected);
+}
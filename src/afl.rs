@@ -0,0 +1,209 @@
+//! AFL++ custom mutator
+//!
+//! With the `cdylib` feature the shared library also implements the custom
+//! mutator API of AFL++, mutating and trimming inputs as JSON documents:
+//!
+//! ```text
+//! cargo build --release
+//! AFL_CUSTOM_MUTATOR_LIBRARY=target/release/libbnf.so afl-fuzz -i in -o out -- ./target @@
+//! ```
+//!
+//! Trimming removes one array element or object member at a time, keeping
+//! the removals that don't change the coverage. Inputs that don't parse are
+//! left to the trimming of AFL++.
+//!
+//! AFL++ still runs its own byte-level stages, which break the syntax, unless
+//! `AFL_CUSTOM_MUTATOR_ONLY` is set. Setting `BNF_VALID_ONLY` as well makes
+//! `afl_custom_post_process` replace any input that isn't valid JSON by a
+//! fresh document, so the target only ever sees valid ones.
+use core::ffi::c_void;
+
+use crate::libfuzzer::input;
+use crate::mutate::{count, crossover, generate, mutate, remove};
+use crate::value::Value;
+use crate::{Config, Rng};
+
+/// Largest input `afl_custom_post_process` generates
+const MAX_FRESH: usize = 1 << 16;
+
+/// State of the mutator, opaque to AFL++
+struct Mutator {
+    rng: Rng,
+    config: Config,
+
+    /// Output buffer reused across calls, AFL++ reads it until the next one
+    out: Vec<u8>,
+
+    /// Whether `afl_custom_post_process` replaces invalid inputs
+    valid_only: bool,
+    trim: Option<Trim>,
+}
+
+/// Progress of trimming an input
+struct Trim {
+    /// Input as trimmed so far
+    doc: Value,
+
+    /// `doc` with value number `step` removed, being tried
+    candidate: Value,
+
+    /// Value considered for removal, in pre-order
+    step: usize,
+
+    /// Steps announced to AFL++ by `afl_custom_init_trim`
+    steps: usize,
+}
+
+/// Create the mutator, seeding it with `seed`
+#[no_mangle]
+pub extern "C" fn afl_custom_init(_afl: *mut c_void, seed: u32) -> *mut c_void {
+    let mutator = Mutator {
+        rng: Rng::seeded(seed as u64),
+        config: Config::default(),
+        out: Vec::new(),
+        valid_only: std::env::var_os("BNF_VALID_ONLY").is_some(),
+        trim: None,
+    };
+    Box::into_raw(Box::new(mutator)) as *mut c_void
+}
+
+/// Mutate `buf`, or cross it over with `add_buf` if there is one, into at
+/// most `max_size` bytes pointed to by `*out_buf`, returning their number
+///
+/// # Safety
+///
+/// `data` must come from `afl_custom_init`, `buf` and `add_buf` must be
+/// valid for reads of `buf_size` and `add_buf_size` bytes and `out_buf` for
+/// a write.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_fuzz(
+    data: *mut c_void,
+    buf: *mut u8,
+    buf_size: usize,
+    out_buf: *mut *mut u8,
+    add_buf: *mut u8,
+    add_buf_size: usize,
+    max_size: usize,
+) -> usize {
+    let m = &mut *(data as *mut Mutator);
+    let buf = input(buf, buf_size);
+    if !add_buf.is_null() && m.rng.next() % 4 == 1 {
+        let add_buf = input(add_buf, add_buf_size);
+        crossover(buf, add_buf, &mut m.rng, &m.config, max_size, &mut m.out);
+    } else {
+        mutate(buf, &mut m.rng, &m.config, max_size, &mut m.out);
+    }
+    *out_buf = m.out.as_mut_ptr();
+    m.out.len()
+}
+
+/// Input actually given to the target for `buf`: `buf` itself, unless
+/// `BNF_VALID_ONLY` is set and it isn't valid JSON
+///
+/// # Safety
+///
+/// `data` must come from `afl_custom_init`, `buf` must be valid for reads
+/// of `buf_size` bytes and `out_buf` for a write.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_post_process(
+    data: *mut c_void,
+    buf: *mut u8,
+    buf_size: usize,
+    out_buf: *mut *mut u8,
+) -> usize {
+    let m = &mut *(data as *mut Mutator);
+    if !m.valid_only || Value::parse(input(buf, buf_size)).is_ok() {
+        *out_buf = buf;
+        return buf_size;
+    }
+    let limit = buf_size.clamp(16, MAX_FRESH);
+    generate(&mut m.rng, &m.config, limit, &mut m.out);
+    *out_buf = m.out.as_mut_ptr();
+    m.out.len()
+}
+
+/// Start trimming `buf`, returning the number of steps, 0 if it doesn't
+/// parse
+///
+/// # Safety
+///
+/// `data` must come from `afl_custom_init` and `buf` must be valid for reads
+/// of `buf_size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_init_trim(
+    data: *mut c_void,
+    buf: *mut u8,
+    buf_size: usize,
+) -> i32 {
+    let m = &mut *(data as *mut Mutator);
+    m.trim = match Value::parse(input(buf, buf_size)) {
+        Ok(doc) if count(&doc) > 1 => {
+            let steps = count(&doc) - 1;
+            Some(Trim {
+                candidate: doc.clone(),
+                doc,
+                step: 1,
+                steps,
+            })
+        }
+        _ => None,
+    };
+    m.trim
+        .as_ref()
+        .map_or(0, |trim| trim.steps.min(i32::MAX as usize) as i32)
+}
+
+/// Next trimming candidate, pointed to by `*out_buf`, returning its size
+///
+/// # Safety
+///
+/// `data` must come from `afl_custom_init` and trimming must have been
+/// started with `afl_custom_init_trim`. `out_buf` must be valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_trim(data: *mut c_void, out_buf: *mut *mut u8) -> usize {
+    let m = &mut *(data as *mut Mutator);
+    let trim = m.trim.as_mut().expect("trimming wasn't started");
+    trim.candidate = trim.doc.clone();
+    // Earlier removals may have left fewer values than announced
+    if trim.step < count(&trim.doc) {
+        remove(&mut trim.candidate, trim.step);
+    }
+    m.out.clear();
+    trim.candidate.write(&mut m.out);
+    *out_buf = m.out.as_mut_ptr();
+    m.out.len()
+}
+
+/// Keep the last candidate if `success`, returning the index of the next
+/// step
+///
+/// # Safety
+///
+/// `data` must come from `afl_custom_init` and trimming must have been
+/// started with `afl_custom_init_trim`.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_post_trim(data: *mut c_void, success: u8) -> i32 {
+    let m = &mut *(data as *mut Mutator);
+    let trim = m.trim.as_mut().expect("trimming wasn't started");
+    if success != 0 {
+        // The next value moved into the place of the removed one
+        core::mem::swap(&mut trim.doc, &mut trim.candidate);
+    } else {
+        trim.step += 1;
+    }
+    let step = match trim.step < count(&trim.doc) {
+        true => trim.step - 1,
+        false => trim.steps,
+    };
+    step.min(i32::MAX as usize) as i32
+}
+
+/// Free the mutator
+///
+/// # Safety
+///
+/// `data` must come from `afl_custom_init` and isn't valid anymore.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_deinit(data: *mut c_void) {
+    drop(Box::from_raw(data as *mut Mutator));
+}
@@ -0,0 +1,194 @@
+//! Batches of inputs handed from the workers to the collector
+//!
+//! A worker appends its inputs back to back to the arena of a `Batch`,
+//! recording where each one ends, and hands over the whole batch at once.
+//! That is one channel send for hundreds of inputs instead of an allocation
+//! and a send for each. Emptied batches travel back to the workers through a
+//! `Pool`, so the arenas keep their capacity and the steady state doesn't
+//! allocate at all.
+//!
+//! Every generation attempt has a sequence number and a batch covers a range
+//! of them, so the collector can put batches from different workers back in
+//! order.
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+
+/// Arena size past which a batch is handed over
+pub const BATCH_BYTES: usize = 1 << 20;
+
+/// Sequence numbers claimed by a worker at once, and so the most a batch
+/// covers, so batches of slow or tiny inputs don't hold the collector back
+pub const BATCH_INPUTS: u64 = 1024;
+
+/// Generation parameters of an input in a batch
+#[derive(Debug, Clone, Copy)]
+pub struct Input {
+    /// Offset in the arena one past the last byte of the input
+    end: usize,
+
+    /// Seed of the `Rng` the input was generated with
+    pub seed: u64,
+
+    /// Depth counter reached by the derivation
+    pub depth: u64,
+}
+
+/// Inputs stored back to back in one arena
+#[derive(Debug)]
+pub struct Batch {
+    data: Vec<u8>,
+    inputs: Vec<Input>,
+
+    /// Sequence number of the first attempt in the batch
+    pub start: u64,
+
+    /// Sequence number one past the last attempt in the batch, committed or
+    /// discarded
+    pub end: u64,
+}
+
+impl Batch {
+    fn new() -> Batch {
+        Batch {
+            data: Vec::with_capacity(BATCH_BYTES),
+            inputs: Vec::with_capacity(BATCH_INPUTS as usize),
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// End of the last committed input
+    fn committed(&self) -> usize {
+        self.inputs.last().map_or(0, |input| input.end)
+    }
+
+    /// Buffer to append the next input to, followed by `commit` or `discard`
+    pub fn arena(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    /// Keep everything appended since the last input as a new input, ending
+    /// the current attempt
+    pub fn commit(&mut self, seed: u64, depth: u64) {
+        self.inputs.push(Input {
+            end: self.data.len(),
+            seed,
+            depth,
+        });
+        self.end += 1;
+    }
+
+    /// Drop everything appended since the last input, ending the current
+    /// attempt
+    pub fn discard(&mut self) {
+        self.data.truncate(self.committed());
+        self.end += 1;
+    }
+
+    /// Whether the batch covers no attempt at all
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+
+    /// Whether the arena is large enough to be handed over
+    pub fn is_full(&self) -> bool {
+        self.data.len() >= BATCH_BYTES
+    }
+
+    /// Bytes and parameters of every input, in generation order
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &Input)> {
+        let starts = core::iter::once(0).chain(self.inputs.iter().map(|input| input.end));
+        starts
+            .zip(&self.inputs)
+            .map(move |(start, input)| (&self.data[start..input.end], input))
+    }
+}
+
+/// Emptied batches on their way back to the workers
+#[derive(Clone)]
+pub struct Pool {
+    tx: SyncSender<Batch>,
+    rx: Arc<Mutex<Receiver<Batch>>>,
+}
+
+impl Pool {
+    /// Pool keeping at most `capacity` idle batches
+    pub fn new(capacity: usize) -> Pool {
+        let (tx, rx) = sync_channel(capacity);
+        Pool {
+            tx,
+            rx: Arc::new(Mutex::new(rx)),
+        }
+    }
+
+    /// An idle batch if there is one, otherwise a new one, starting at
+    /// sequence number `start`
+    pub fn get(&self, start: u64) -> Batch {
+        let idle = self.rx.lock().unwrap().try_recv();
+        let mut batch = idle.unwrap_or_else(|_| Batch::new());
+        batch.start = start;
+        batch.end = start;
+        batch
+    }
+
+    /// Empty `batch` and keep it for reuse, unless the pool is full
+    pub fn put(&self, mut batch: Batch) {
+        batch.data.clear();
+        batch.inputs.clear();
+        let _ = self.tx.try_send(batch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit_and_discard() {
+        let mut batch = Pool::new(1).get(10);
+        batch.arena().extend_from_slice(b"first");
+        batch.commit(1, 2);
+        batch.arena().extend_from_slice(b"dropped");
+        batch.discard();
+        batch.arena().extend_from_slice(b"second");
+        batch.commit(3, 4);
+
+        // Discarded attempts still use up a sequence number
+        assert_eq!((batch.start, batch.end), (10, 13));
+        let inputs: Vec<_> = batch
+            .iter()
+            .map(|(data, input)| (data, input.seed, input.depth))
+            .collect();
+        assert_eq!(inputs, [(&b"first"[..], 1, 2), (&b"second"[..], 3, 4)]);
+    }
+
+    #[test]
+    fn full_past_batch_bytes() {
+        let mut batch = Pool::new(1).get(0);
+        assert!(batch.is_empty());
+        batch.arena().resize(BATCH_BYTES - 1, 0);
+        batch.commit(0, 0);
+        assert!(!batch.is_empty() && !batch.is_full());
+        batch.arena().push(0);
+        assert!(batch.is_full());
+    }
+
+    #[test]
+    fn pool_reuses_batches() {
+        let pool = Pool::new(1);
+        let mut batch = pool.get(0);
+        batch.arena().resize(2 * BATCH_BYTES, 1);
+        batch.commit(0, 0);
+        pool.put(batch);
+
+        // Emptied, with the capacity it grew to
+        let batch = pool.get(5);
+        assert!(batch.data.capacity() >= 2 * BATCH_BYTES);
+        assert_eq!((batch.start, batch.end, batch.iter().count()), (5, 5, 0));
+
+        // Only `capacity` idle batches are kept, the large one is dropped
+        pool.put(pool.get(0));
+        pool.put(batch);
+        assert!(pool.get(0).data.capacity() < 2 * BATCH_BYTES);
+    }
+}
@@ -0,0 +1,83 @@
+//! Generation throughput benchmark
+//!
+//! Usage: bench [--iterative] [RULE] [THREADS] [MAX_DEPTH]
+//!
+//! Generates from RULE (`Json` by default) on THREADS threads (1 by default)
+//! until Ctrl-C, printing the cycles spent per generated byte. With
+//! `--iterative` the rules of `bnf::iterative` are used instead of the
+//! recursive ones, for comparison.
+use std::process;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use bnf::interrupt::{catch_interrupt, interrupted};
+use bnf::{Generator, MAX_DEPTH};
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let iterative = args.first().map(String::as_str) == Some("--iterative");
+    if iterative {
+        args.remove(0);
+    }
+    let arg = |i: usize, default: u64| match args.get(i) {
+        None => default,
+        Some(arg) => arg.parse().unwrap_or_else(|_| {
+            eprintln!("error: {} isn't a number", arg);
+            process::exit(2);
+        }),
+    };
+    let rule = args.first().map_or("Json", String::as_str);
+    let threads = arg(1, 1);
+    let lookup = if iterative {
+        bnf::iterative::rule
+    } else {
+        bnf::json4::rule
+    };
+    let rule = lookup(rule).unwrap_or_else(|| {
+        eprintln!("error: unknown rule {}", rule);
+        process::exit(2);
+    });
+    let generator = Generator::new().rule_fn(rule).max_depth(arg(2, MAX_DEPTH));
+
+    // Channel used to send the sizes of the inputs to the collector
+    let (tx, rx) = channel();
+
+    catch_interrupt();
+    let start = unsafe { core::arch::x86_64::_rdtsc() };
+
+    // Start the worker threads, each with its own sequence of inputs
+    for thread in 0..threads {
+        let tx = tx.clone();
+        let mut generator = generator.clone().seed(start ^ thread);
+        thread::spawn(move || {
+            let mut buf = Vec::with_capacity(MAX_DEPTH as usize * 1024);
+            loop {
+                buf.clear();
+                generator.generate(&mut buf);
+                if tx.send(buf.len()).is_err() || interrupted() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // No need for the tx channel side anymore since we have no more threads
+    drop(tx);
+
+    let mut generated_bytes = 0;
+    let mut counter = 0u64;
+    for size in rx.iter() {
+        generated_bytes += size;
+        counter += 1;
+
+        let elapsed = unsafe { core::arch::x86_64::_rdtsc() } - start;
+        if counter & 0xffff == 0 {
+            println!(
+                "Time: {:10.2?} Mcycle / {:10.2} MB = {:10.4} cycle/byte",
+                elapsed as f64 / 1000. / 1000.,
+                generated_bytes as f64 / 1000. / 1000.,
+                elapsed as f64 / generated_bytes as f64,
+            );
+        }
+    }
+}
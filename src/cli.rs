@@ -0,0 +1,426 @@
+//! Command line of the generator binary
+//!
+//! Parsed by hand to keep the binary free of dependencies. Options take their
+//! value either as the next argument or after `=`, as in `--count=10`.
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use bnf::json4::{KeyMode, VALUE_NAMES};
+use bnf::Config;
+
+pub const USAGE: &str = "\
+Usage: bnf [OPTIONS] [-- PROGRAM [ARGS]...]...
+
+Generate inputs from a grammar, by default without writing them anywhere, as a
+throughput benchmark. Runs until a limit is reached or Ctrl-C, after which the
+inputs already generated are written out. A second Ctrl-C stops immediately.
+
+With a PROGRAM, run it on each input instead, saving the inputs it crashes on
+(dies from a signal) or hangs on. It gets the input on stdin, or in a file if
+an argument contains `@@`, which is replaced by the path of the file.
+
+With several PROGRAMs, or a PROGRAM and --fuzz-parser, compare them instead:
+inputs some accept (exit code 0) and others reject, or that they print
+different output for, are saved as mismatches. Output that is JSON is compared
+in a canonical form.
+
+Grammar:
+  -g, --grammar <NAME>     Grammar to generate from: json [default: json]
+  -r, --rule <RULE>        Start rule of the grammar [default: Json]
+      --iterative          Generate with an explicit stack instead of
+                           recursion, for a --max-depth in the millions. The
+                           inputs are the same
+      --schema <FILE>      Generate instances of a JSON Schema instead
+  -m, --mode <MODE>        valid, invalid (near-misses or instances violating
+                           the schema once), oracle (input, tab, expected
+                           value) or float (number, tab, f64 and f32 bits)
+                           [default: valid]
+
+Limits:
+  -n, --count <N>          Stop after N inputs
+  -t, --time <SECS>        Stop after SECS seconds
+  -b, --bytes <N>          Stop after N bytes of output
+      --max-depth <N>      Depth past which rules stop recursing [default: 128]
+      --max-repeat <N>     Bound on repetitions of `*` and `+` [default: 16]
+      --keys <POLICY>      Object keys: random, unique or duplicate[:PERCENT]
+                           [default: random]
+      --weights <LIST>     Relative weights of json values, overriding the
+                           defaults string=15,number=15,object=30,array=38,
+                           true=1,false=1,null=1, e.g. null=0,array=60
+
+Execution and output:
+  -j, --threads <N>        Worker threads [default: 1]
+  -s, --seed <N>           Master seed, for reproducible runs. The output only
+                           depends on the seed, not on --threads
+  -o, --output <PATH>      Write inputs to PATH, `-` for stdout
+      --corpus <DIR>       Write each input to its own file in DIR, named
+                           `id:000123,seed:SEED`
+      --meta               Record how each corpus input was generated in
+                           DIR/.meta
+      --dedup              Skip inputs identical to an earlier one
+      --shm <PATH>         Write inputs to a shared-memory ring buffer at PATH,
+                           e.g. /dev/shm/bnf, for another process to read
+      --read-shm <PATH>    Read the inputs from the ring buffer at PATH and
+                           write them to --output, as a reference consumer
+      --serve <SOCKET>     Serve requests for inputs on a Unix socket instead,
+                           with the other options as defaults for requests
+      --fuzz-parser        Fuzz the built-in JSON parser in-process with the
+                           inputs instead of writing them, as an example of
+                           the harness API in src/harness.rs, or compare it
+                           with PROGRAM
+      --crashes <DIR>      Where --fuzz-parser and PROGRAM runs save the
+                           inputs the target crashes on, only the smallest of
+                           those with the same signal and innermost stack
+                           frames [default: crashes]
+      --hangs <DIR>        Where PROGRAM runs save the inputs the target times
+                           out on [default: hangs]
+      --timeout <MS>       Kill PROGRAM after MS milliseconds [default: 1000]
+      --env <NAME>         Pass the input to PROGRAM in the environment
+                           variable NAME instead
+      --crash-exit <LIST>  Exit codes of PROGRAM that count as crashes too,
+                           e.g. 1,77
+      --mismatches <DIR>   Where comparisons save the inputs the targets
+                           disagree on [default: mismatches]
+      --replay <SEED>      Regenerate the single input with seed SEED, to
+                           stdout unless --output is given
+  -f, --format <FORMAT>    Framing of the inputs in the output [default: raw]
+                             raw     back to back
+                             lines   each followed by a newline, for inputs
+                                     without raw newlines (only invalid json
+                                     inputs can have them)
+                             nul     each followed by a NUL byte
+                             length  each preceded by its length as a
+                                     little-endian u32
+      --stats              Report throughput on stderr
+  -h, --help               Print this help
+";
+
+/// What is generated for each input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Valid,
+    Invalid,
+    Oracle,
+    Float,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "valid" => Some(Mode::Valid),
+            "invalid" => Some(Mode::Invalid),
+            "oracle" => Some(Mode::Oracle),
+            "float" => Some(Mode::Float),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Valid => "valid",
+            Mode::Invalid => "invalid",
+            Mode::Oracle => "oracle",
+            Mode::Float => "float",
+        }
+    }
+}
+
+/// How consecutive inputs are separated in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Back to back, without separator
+    Raw,
+
+    /// Each followed by `'\n'`
+    Lines,
+
+    /// Each followed by `'\0'`
+    Nul,
+
+    /// Each preceded by its length as a little-endian `u32`
+    Length,
+}
+
+impl Format {
+    /// Write `input` to `out` with its framing
+    pub fn write(self, out: &mut dyn Write, input: &[u8]) -> io::Result<()> {
+        match self {
+            Format::Raw => out.write_all(input),
+            Format::Lines => {
+                out.write_all(input)?;
+                out.write_all(b"\n")
+            }
+            Format::Nul => {
+                out.write_all(input)?;
+                out.write_all(b"\0")
+            }
+            Format::Length => {
+                let len = u32::try_from(input.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "input longer than 4 GiB")
+                })?;
+                out.write_all(&len.to_le_bytes())?;
+                out.write_all(input)
+            }
+        }
+    }
+}
+
+/// Parsed command line
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub grammar: String,
+    pub rule: String,
+    pub iterative: bool,
+    pub schema: Option<String>,
+    pub mode: Mode,
+    pub count: Option<u64>,
+    pub time: Option<Duration>,
+    pub bytes: Option<u64>,
+    pub threads: usize,
+    pub seed: Option<u64>,
+
+    /// `None` to discard the inputs, `Some("-")` for stdout
+    pub output: Option<String>,
+    pub format: Format,
+    pub corpus: Option<String>,
+    pub meta: bool,
+    pub dedup: bool,
+    pub replay: Option<u64>,
+    pub shm: Option<String>,
+    pub read_shm: Option<String>,
+    pub serve: Option<String>,
+    pub fuzz_parser: bool,
+    pub crashes: String,
+    pub hangs: String,
+
+    /// Programs to run on each input with their arguments
+    pub exec: Vec<Vec<String>>,
+    pub mismatches: String,
+    pub timeout: Duration,
+    pub env: Option<String>,
+    pub crash_codes: Vec<i32>,
+    pub config: Config,
+    pub stats: bool,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Args {
+        Args {
+            grammar: "json".to_string(),
+            rule: "Json".to_string(),
+            iterative: false,
+            schema: None,
+            mode: Mode::Valid,
+            count: None,
+            time: None,
+            bytes: None,
+            threads: 1,
+            seed: None,
+            output: None,
+            format: Format::Raw,
+            corpus: None,
+            meta: false,
+            dedup: false,
+            replay: None,
+            shm: None,
+            read_shm: None,
+            serve: None,
+            fuzz_parser: false,
+            crashes: "crashes".to_string(),
+            hangs: "hangs".to_string(),
+            exec: Vec::new(),
+            mismatches: "mismatches".to_string(),
+            timeout: bnf::exec::TIMEOUT,
+            env: None,
+            crash_codes: Vec::new(),
+            config: Config::default(),
+            stats: false,
+            help: false,
+        }
+    }
+}
+
+impl Args {
+    /// Parse the arguments following the program name
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+        let mut res = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.find('=') {
+                Some(pos) if arg.starts_with("--") => {
+                    (&arg[..pos], Some(arg[pos + 1..].to_string()))
+                }
+                _ => (arg.as_str(), None),
+            };
+
+            // Flags
+            match name {
+                "--" => {
+                    let program: Vec<String> =
+                        args.by_ref().take_while(|arg| arg != "--").collect();
+                    if program.is_empty() {
+                        return Err("-- needs a program".to_string());
+                    }
+                    res.exec.push(program);
+                    continue;
+                }
+                "-h" | "--help" => {
+                    res.help = true;
+                    continue;
+                }
+                "--stats" => {
+                    res.stats = true;
+                    continue;
+                }
+                "--iterative" => {
+                    res.iterative = true;
+                    continue;
+                }
+                "--meta" => {
+                    res.meta = true;
+                    continue;
+                }
+                "--dedup" => {
+                    res.dedup = true;
+                    continue;
+                }
+                "--fuzz-parser" => {
+                    res.fuzz_parser = true;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if !name.starts_with('-') {
+                return Err(format!("unexpected argument {}", name));
+            }
+            let mut inline = inline;
+            let mut value = || {
+                inline
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} needs a value", name))
+            };
+            match name {
+                "-g" | "--grammar" => res.grammar = value()?,
+                "-r" | "--rule" => res.rule = value()?,
+                "--schema" => res.schema = Some(value()?),
+                "-m" | "--mode" => {
+                    let value = value()?;
+                    res.mode =
+                        Mode::from_name(&value).ok_or_else(|| format!("unknown mode {}", value))?;
+                }
+                "-n" | "--count" => res.count = Some(number(name, &value()?)?),
+                "-t" | "--time" => {
+                    let secs: f64 = number(name, &value()?)?;
+                    if !secs.is_finite() || secs < 0. {
+                        return Err(format!("{} needs a non-negative duration", name));
+                    }
+                    res.time = Some(Duration::from_secs_f64(secs));
+                }
+                "-b" | "--bytes" => res.bytes = Some(number(name, &value()?)?),
+                "-j" | "--threads" => {
+                    res.threads = number(name, &value()?)?;
+                    if res.threads == 0 {
+                        return Err(format!("{} needs at least one thread", name));
+                    }
+                }
+                "-s" | "--seed" => res.seed = Some(number(name, &value()?)?),
+                "-o" | "--output" => res.output = Some(value()?),
+                "--corpus" => res.corpus = Some(value()?),
+                "--replay" => res.replay = Some(number(name, &value()?)?),
+                "--shm" => res.shm = Some(value()?),
+                "--read-shm" => res.read_shm = Some(value()?),
+                "--serve" => res.serve = Some(value()?),
+                "--crashes" => res.crashes = value()?,
+                "--hangs" => res.hangs = value()?,
+                "--mismatches" => res.mismatches = value()?,
+                "--timeout" => res.timeout = Duration::from_millis(number(name, &value()?)?),
+                "--env" => res.env = Some(value()?),
+                "--crash-exit" => {
+                    res.crash_codes = value()?
+                        .split(',')
+                        .map(|code| number(name, code))
+                        .collect::<Result<_, _>>()?
+                }
+                "-f" | "--format" => {
+                    let value = value()?;
+                    res.format = match value.as_str() {
+                        "raw" => Format::Raw,
+                        "lines" => Format::Lines,
+                        "nul" => Format::Nul,
+                        "length" => Format::Length,
+                        _ => return Err(format!("unknown format {}", value)),
+                    }
+                }
+                "--max-depth" => res.config.max_depth = number(name, &value()?)?,
+                "--max-repeat" => {
+                    res.config.max_repeat = number(name, &value()?)?;
+                    if res.config.max_repeat == 0 {
+                        return Err(format!("{} needs to be at least 1", name));
+                    }
+                }
+                "--keys" => res.config.key_mode = key_mode(&value()?)?,
+                "--weights" => {
+                    for item in value()?.split(',') {
+                        let (alt, weight) = item
+                            .split_once('=')
+                            .ok_or_else(|| format!("{} needs NAME=WEIGHT pairs", name))?;
+                        set_weight(&mut res.config, alt, number(name, weight)?)?;
+                    }
+                    check_weights(&res.config)?;
+                }
+                _ => return Err(format!("unknown option {}", name)),
+            }
+        }
+        if res.meta && res.corpus.is_none() {
+            return Err("--meta needs --corpus".to_string());
+        }
+        Ok(res)
+    }
+}
+
+fn number<T: core::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, name))
+}
+
+/// `random`, `unique`, `duplicate` or `duplicate:PERCENT`
+pub fn key_mode(value: &str) -> Result<KeyMode, String> {
+    match value {
+        "random" => Ok(KeyMode::Random),
+        "unique" => Ok(KeyMode::Unique),
+        "duplicate" => Ok(KeyMode::Duplicate(50)),
+        _ => match value.strip_prefix("duplicate:") {
+            Some(percent) => {
+                let percent = number("--keys", percent)?;
+                if percent > 100 {
+                    return Err("--keys needs a percentage up to 100".to_string());
+                }
+                Ok(KeyMode::Duplicate(percent))
+            }
+            None => Err(format!("unknown key policy {}", value)),
+        },
+    }
+}
+
+/// Set the weight of the `JsonValue` alternative named `alt`
+pub fn set_weight(config: &mut Config, alt: &str, weight: u32) -> Result<(), String> {
+    match VALUE_NAMES.iter().position(|&name| name == alt) {
+        Some(index) => {
+            config.value_weights[index] = weight;
+            Ok(())
+        }
+        None => Err(format!("unknown json value {}", alt)),
+    }
+}
+
+pub fn check_weights(config: &Config) -> Result<(), String> {
+    if config.value_weights.iter().all(|&weight| weight == 0) {
+        return Err("weights can't all be zero".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,180 @@
+//! Corpus directory output
+//!
+//! Every input becomes its own file, named like the queue entries of AFL
+//! (`id:000123,seed:8761264539`), so the directory can be handed to a fuzzer
+//! as its initial corpus. Optional sidecars recording how each input was
+//! generated go to the `.meta` subdirectory, which fuzzers skip since it
+//! isn't a regular file.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::value::write_string;
+
+/// Generation parameters of one input
+#[derive(Debug, Clone, Copy)]
+pub struct Meta<'a> {
+    /// Seed of the input, `--replay` regenerates it
+    pub seed: u64,
+    pub grammar: &'a str,
+    pub rule: &'a str,
+    pub mode: &'a str,
+
+    /// Depth counter reached by the derivation
+    pub depth: u64,
+    pub max_depth: u64,
+    pub max_repeat: usize,
+}
+
+impl Meta<'_> {
+    /// JSON object with the parameters and the `size` of the input
+    fn write(&self, size: usize, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(format!("{{\"seed\":{},\"grammar\":", self.seed).as_bytes());
+        write_string(self.grammar, buf);
+        buf.extend_from_slice(b",\"rule\":");
+        write_string(self.rule, buf);
+        buf.extend_from_slice(b",\"mode\":");
+        write_string(self.mode, buf);
+        let numbers = format!(
+            ",\"depth\":{},\"max_depth\":{},\"max_repeat\":{},\"size\":{}}}\n",
+            self.depth, self.max_depth, self.max_repeat, size
+        );
+        buf.extend_from_slice(numbers.as_bytes());
+    }
+}
+
+/// Directory receiving one file per input
+pub struct Corpus {
+    dir: PathBuf,
+
+    /// Whether sidecars are written
+    meta: bool,
+
+    /// Id of the next input
+    next_id: u64,
+}
+
+impl Corpus {
+    /// Open `dir`, creating it if needed
+    ///
+    /// Ids continue after the highest one already in the directory, so runs
+    /// can add to an existing corpus.
+    pub fn open(dir: &Path, meta: bool) -> io::Result<Corpus> {
+        fs::create_dir_all(dir)?;
+        if meta {
+            fs::create_dir_all(dir.join(".meta"))?;
+        }
+
+        let mut next_id = 0;
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            let id = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("id:"))
+                .and_then(|name| name.split(',').next())
+                .and_then(|id| id.parse::<u64>().ok());
+            if let Some(id) = id {
+                next_id = next_id.max(id + 1);
+            }
+        }
+
+        Ok(Corpus {
+            dir: dir.to_path_buf(),
+            meta,
+            next_id,
+        })
+    }
+
+    /// Write `input` as the next file of the corpus
+    pub fn save(&mut self, input: &[u8], meta: &Meta) -> io::Result<()> {
+        self.save_tagged(input, meta, "").map(drop)
+    }
+
+    /// Write `input` as the next file of the corpus, with `tags` such as
+    /// `"sig:11,"` between the id and the seed in its name, and return the
+    /// name
+    pub fn save_tagged(&mut self, input: &[u8], meta: &Meta, tags: &str) -> io::Result<String> {
+        let name = format!("id:{:06},{}seed:{}", self.next_id, tags, meta.seed);
+        fs::write(self.dir.join(&name), input)?;
+        if self.meta {
+            let mut sidecar = Vec::new();
+            meta.write(input.len(), &mut sidecar);
+            fs::write(self.dir.join(".meta").join(&name), sidecar)?;
+        }
+        self.next_id += 1;
+        Ok(name)
+    }
+
+    /// Delete the input called `name` and its sidecar
+    pub fn remove(&mut self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.dir.join(name))?;
+        match fs::remove_file(self.dir.join(".meta").join(name)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Filter for inputs already seen, keeping only a 64-bit hash of each
+#[derive(Debug, Default)]
+pub struct Dedup {
+    seen: HashSet<u64>,
+}
+
+impl Dedup {
+    /// Returns `true` the first time `input` is seen
+    pub fn insert(&mut self, input: &[u8]) -> bool {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(input);
+        self.seen.insert(hasher.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedup() {
+        let mut dedup = Dedup::default();
+        assert!(dedup.insert(b"[1]"));
+        assert!(dedup.insert(b"[1] "));
+        assert!(dedup.insert(b""));
+        assert!(!dedup.insert(b"[1]"));
+        assert!(!dedup.insert(b""));
+    }
+
+    #[test]
+    fn ids_continue_across_runs() {
+        let dir = std::env::temp_dir().join(format!("bnf-corpus-{}", std::process::id()));
+        let meta = Meta {
+            seed: 42,
+            grammar: "json",
+            rule: "Json",
+            mode: "valid",
+            depth: 3,
+            max_depth: 128,
+            max_repeat: 16,
+        };
+        let mut corpus = Corpus::open(&dir, true).unwrap();
+        corpus.save(b"[]", &meta).unwrap();
+        let name = corpus.save_tagged(b"{}", &meta, "sig:11,").unwrap();
+        assert_eq!(name, "id:000001,sig:11,seed:42");
+        let sidecar = fs::read(dir.join(".meta").join(&name)).unwrap();
+        assert!(sidecar.ends_with(b"\"size\":2}\n"));
+
+        corpus.remove("id:000000,seed:42").unwrap();
+        assert!(!dir.join(".meta/id:000000,seed:42").exists());
+
+        // A new run continues after the highest id in the directory
+        let mut corpus = Corpus::open(&dir, false).unwrap();
+        corpus.save(b"0", &meta).unwrap();
+        assert_eq!(fs::read(dir.join("id:000002,seed:42")).unwrap(), b"0");
+        assert!(!dir.join(".meta/id:000002,seed:42").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,554 @@
+//! Exact decimal numbers
+//!
+//! JSON numbers are arbitrary precision decimals, so they are kept as digits
+//! and a power of ten instead of being rounded to a float. Conversion to
+//! `f64`/`f32` is done exactly with big integers and rounds to nearest, ties
+//! to even, which makes it usable as ground truth for float parsers.
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+
+use crate::Rng;
+
+/// Number `(-1)^negative * digits * 10^exponent`, normalized so that equal
+/// values compare equal (apart from the sign of zero)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    pub negative: bool,
+
+    /// Significant digits as values 0-9, without leading or trailing zeros.
+    /// Empty for zero.
+    pub digits: Vec<u8>,
+
+    /// Power of ten applied to `digits` read as an integer
+    pub exponent: i64,
+}
+
+impl Decimal {
+    /// Parse a JSON number literal
+    ///
+    /// Exponents too large for an `i64` saturate, which is still far beyond
+    /// the range of any float.
+    pub fn parse(literal: &[u8]) -> Option<Decimal> {
+        let mut pos = 0;
+        let negative = literal.first() == Some(&b'-');
+        if negative {
+            pos += 1;
+        }
+
+        // INT ('.' [0-9]+)?
+        let mut digits = Vec::new();
+        let int_start = pos;
+        while let Some(c @ b'0'..=b'9') = literal.get(pos) {
+            digits.push(c - b'0');
+            pos += 1;
+        }
+        if pos == int_start {
+            return None;
+        }
+        let mut exponent: i64 = 0;
+        if literal.get(pos) == Some(&b'.') {
+            pos += 1;
+            let frac_start = pos;
+            while let Some(c @ b'0'..=b'9') = literal.get(pos) {
+                digits.push(c - b'0');
+                exponent -= 1;
+                pos += 1;
+            }
+            if pos == frac_start {
+                return None;
+            }
+        }
+
+        // EXP?
+        if let Some(b'e') | Some(b'E') = literal.get(pos) {
+            pos += 1;
+            let exp_negative = literal.get(pos) == Some(&b'-');
+            if let Some(b'+') | Some(b'-') = literal.get(pos) {
+                pos += 1;
+            }
+            let exp_start = pos;
+            let mut exp: i64 = 0;
+            while let Some(c @ b'0'..=b'9') = literal.get(pos) {
+                exp = exp.saturating_mul(10).saturating_add((c - b'0') as i64);
+                pos += 1;
+            }
+            if pos == exp_start {
+                return None;
+            }
+            exponent = if exp_negative {
+                exponent.saturating_sub(exp)
+            } else {
+                exponent.saturating_add(exp)
+            };
+        }
+
+        if pos != literal.len() {
+            return None;
+        }
+
+        Some(Decimal::new(negative, digits, exponent))
+    }
+
+    /// Normalize the given digits (values 0-9) and exponent
+    pub fn new(negative: bool, mut digits: Vec<u8>, mut exponent: i64) -> Decimal {
+        let leading = digits.iter().take_while(|&&d| d == 0).count();
+        digits.drain(..leading);
+        while digits.last() == Some(&0) {
+            digits.pop();
+            exponent = exponent.saturating_add(1);
+        }
+        if digits.is_empty() {
+            exponent = 0;
+        }
+        Decimal {
+            negative,
+            digits,
+            exponent,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    /// Exact value if it is an integer that fits a `u64`
+    pub fn to_u64(&self) -> Option<u64> {
+        if self.is_zero() {
+            return Some(0);
+        }
+        if self.negative || self.exponent < 0 {
+            return None;
+        }
+        let mut res: u64 = 0;
+        for &digit in &self.digits {
+            res = res.checked_mul(10)?.checked_add(digit as u64)?;
+        }
+        for _ in 0..self.exponent {
+            res = res.checked_mul(10)?;
+        }
+        Some(res)
+    }
+
+    /// Nearest `f64`
+    pub fn to_f64(&self) -> f64 {
+        f64::from_bits(self.to_f64_bits())
+    }
+
+    /// Bit pattern of the correctly rounded `f64`
+    pub fn to_f64_bits(&self) -> u64 {
+        self.round(&F64)
+    }
+
+    /// Bit pattern of the correctly rounded `f32`
+    pub fn to_f32_bits(&self) -> u32 {
+        self.round(&F32) as u32
+    }
+
+    /// Exact value of `(-1)^negative * mantissa * 2^exp2`
+    ///
+    /// Every float is such a dyadic number, as are the halfway points between
+    /// two neighbouring floats.
+    pub fn from_dyadic(negative: bool, mantissa: u64, exp2: i64) -> Decimal {
+        let mut num = Big::from_u64(mantissa);
+        if exp2 >= 0 {
+            num.shl(exp2 as u64);
+            return Decimal::new(negative, num.to_digits(), 0);
+        }
+
+        // m / 2^n == m * 5^n / 10^n
+        for _ in 0..-exp2 {
+            num.mul_small(5);
+        }
+        Decimal::new(negative, num.to_digits(), exp2)
+    }
+
+    /// Round to the given float format, returning its bit pattern
+    fn round(&self, format: &Format) -> u64 {
+        let sign = (self.negative as u64) << (format.mantissa_bits + format.exponent_bits);
+        if self.is_zero() {
+            return sign;
+        }
+
+        // Values are within [10^(magnitude - 1), 10^magnitude). Far outside
+        // of the float range there is no need for exact arithmetic.
+        let magnitude = self.exponent.saturating_add(self.digits.len() as i64);
+        if magnitude > 310 {
+            return sign | format.infinity();
+        }
+        if magnitude < -330 {
+            return sign;
+        }
+
+        // Reduce the value to `q * 2^exp2`, `sticky` set if that was inexact.
+        // `q` keeps at least 62 significant bits, more than any format needs
+        // to round correctly.
+        let mut num = Big::from_digits(&self.digits);
+        let (q, exp2, sticky) = if self.exponent >= 0 {
+            num.mul_pow10(self.exponent as u64);
+            let bits = num.bits();
+            if bits <= 64 {
+                (num.low_u64(), 0, false)
+            } else {
+                let shift = bits - 64;
+                let sticky = num.any_below(shift);
+                num.shr(shift);
+                (num.low_u64(), shift as i64, sticky)
+            }
+        } else {
+            let mut den = Big::from_u64(1);
+            den.mul_pow10(-self.exponent as u64);
+
+            // Scale so that num / den lands in [2^61, 2^63)
+            let k = 62 - (num.bits() as i64 - den.bits() as i64);
+            if k >= 0 {
+                num.shl(k as u64);
+            } else {
+                den.shl(-k as u64);
+            }
+
+            let mut q = 0u64;
+            den.shl(62);
+            for i in (0..63).rev() {
+                if num.cmp(&den) != Ordering::Less {
+                    num.sub(&den);
+                    q |= 1 << i;
+                }
+                den.shr(1);
+            }
+            (q, -k, !num.is_zero())
+        };
+
+        sign | format.round(q, exp2, sticky)
+    }
+}
+
+/// Binary floating point format
+pub(crate) struct Format {
+    /// Stored mantissa bits, without the implicit leading one
+    pub(crate) mantissa_bits: i64,
+    pub(crate) exponent_bits: i64,
+}
+
+pub(crate) const F64: Format = Format {
+    mantissa_bits: 52,
+    exponent_bits: 11,
+};
+
+pub(crate) const F32: Format = Format {
+    mantissa_bits: 23,
+    exponent_bits: 8,
+};
+
+impl Format {
+    fn bias(&self) -> i64 {
+        (1 << (self.exponent_bits - 1)) - 1
+    }
+
+    fn infinity(&self) -> u64 {
+        ((1 << self.exponent_bits) - 1) << self.mantissa_bits
+    }
+
+    /// Largest finite bit pattern
+    pub(crate) fn max_bits(&self) -> u64 {
+        self.infinity() - 1
+    }
+
+    /// Bits of a random finite float, subnormals and the extremes included
+    pub(crate) fn random_bits(&self, rng: &mut Rng) -> u64 {
+        let sign = (rng.next() % 2) as u64;
+        let magnitude = match rng.next() % 8 {
+            // Subnormal
+            0 => rng.next() as u64 & ((1 << self.mantissa_bits) - 1),
+            _ => rng.next() as u64 % (self.max_bits() + 1),
+        };
+        sign << (self.mantissa_bits + self.exponent_bits) | magnitude
+    }
+
+    /// Split float bits into sign, mantissa and binary exponent
+    pub(crate) fn decompose(&self, bits: u64) -> (bool, u64, i64) {
+        let negative = bits >> (self.mantissa_bits + self.exponent_bits) & 1 == 1;
+        let exponent = (bits >> self.mantissa_bits) as i64 & ((1 << self.exponent_bits) - 1);
+        let fraction = bits & ((1 << self.mantissa_bits) - 1);
+        let min_exp2 = 1 - self.bias() - self.mantissa_bits;
+        if exponent == 0 {
+            (negative, fraction, min_exp2)
+        } else {
+            (
+                negative,
+                fraction | 1 << self.mantissa_bits,
+                exponent - 1 + min_exp2,
+            )
+        }
+    }
+
+    /// Bits of the positive value `q * 2^exp2`, plus a bit more if `sticky`
+    fn round(&self, q: u64, exp2: i64, sticky: bool) -> u64 {
+        let m = self.mantissa_bits;
+        let emin = 1 - self.bias();
+
+        // Weight of the lowest mantissa bit, fixed for subnormals
+        let leading = 63 - q.leading_zeros() as i64 + exp2;
+        let lsb = (leading - m).max(emin - m);
+        let shift = lsb - exp2;
+
+        let mut mantissa = if shift <= 0 {
+            q << -shift
+        } else {
+            let kept = if shift >= 64 { 0 } else { q >> shift };
+            let half = shift <= 64 && (q >> (shift - 1)) & 1 == 1;
+            let rest = sticky || (shift > 1 && q & (u64::MAX >> (65 - shift.min(64))) != 0);
+            if half && (rest || kept & 1 == 1) {
+                kept + 1
+            } else {
+                kept
+            }
+        };
+
+        // Rounding up can carry into the next binade
+        let mut lsb = lsb;
+        if mantissa >> (m + 1) != 0 {
+            mantissa >>= 1;
+            lsb += 1;
+        }
+
+        if mantissa >> m == 0 {
+            // Subnormal or zero
+            return mantissa;
+        }
+        let exponent = lsb + m;
+        if exponent > self.bias() {
+            return self.infinity();
+        }
+        ((exponent + self.bias()) as u64) << m | (mantissa & ((1 << m) - 1))
+    }
+}
+
+/// Unsigned big integer as little endian 32-bit limbs, just what float
+/// conversion needs
+struct Big(Vec<u32>);
+
+impl Big {
+    fn from_u64(num: u64) -> Big {
+        let mut res = Big(vec![num as u32, (num >> 32) as u32]);
+        res.trim();
+        res
+    }
+
+    fn from_digits(digits: &[u8]) -> Big {
+        let mut res = Big(Vec::new());
+        for chunk in digits.chunks(9) {
+            let mut part = 0;
+            for &digit in chunk {
+                part = part * 10 + digit as u32;
+            }
+            res.mul_small(10u32.pow(chunk.len() as u32));
+            res.add_small(part);
+        }
+        res
+    }
+
+    fn trim(&mut self) {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bits(&self) -> u64 {
+        match self.0.last() {
+            Some(top) => self.0.len() as u64 * 32 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    fn low_u64(&self) -> u64 {
+        let limb = |i| self.0.get(i).copied().unwrap_or(0) as u64;
+        limb(0) | limb(1) << 32
+    }
+
+    fn mul_small(&mut self, factor: u32) {
+        let mut carry = 0u64;
+        for limb in self.0.iter_mut() {
+            let prod = *limb as u64 * factor as u64 + carry;
+            *limb = prod as u32;
+            carry = prod >> 32;
+        }
+        if carry != 0 {
+            self.0.push(carry as u32);
+        }
+        self.trim();
+    }
+
+    fn add_small(&mut self, mut num: u32) {
+        for limb in self.0.iter_mut() {
+            let (sum, overflow) = limb.overflowing_add(num);
+            *limb = sum;
+            if !overflow {
+                return;
+            }
+            num = 1;
+        }
+        if num != 0 {
+            self.0.push(num);
+        }
+    }
+
+    fn mul_pow10(&mut self, mut exp: u64) {
+        while exp >= 9 {
+            self.mul_small(1_000_000_000);
+            exp -= 9;
+        }
+        self.mul_small(10u32.pow(exp as u32));
+    }
+
+    fn shl(&mut self, bits: u64) {
+        if self.is_zero() {
+            return;
+        }
+        let limbs = (bits / 32) as usize;
+        let bits = bits % 32;
+        if bits != 0 {
+            let mut carry = 0;
+            for limb in self.0.iter_mut() {
+                let next = *limb >> (32 - bits);
+                *limb = *limb << bits | carry;
+                carry = next;
+            }
+            if carry != 0 {
+                self.0.push(carry);
+            }
+        }
+        self.0.splice(0..0, core::iter::repeat_n(0, limbs));
+    }
+
+    fn shr(&mut self, bits: u64) {
+        let limbs = ((bits / 32) as usize).min(self.0.len());
+        self.0.drain(..limbs);
+        let bits = bits % 32;
+        if bits != 0 {
+            for i in 0..self.0.len() {
+                let high = self.0.get(i + 1).map_or(0, |&next| next << (32 - bits));
+                self.0[i] = self.0[i] >> bits | high;
+            }
+        }
+        self.trim();
+    }
+
+    /// Whether any of the lowest `bits` bits is set
+    fn any_below(&self, bits: u64) -> bool {
+        let limbs = (bits / 32) as usize;
+        let bits = bits % 32;
+        self.0.iter().take(limbs).any(|&limb| limb != 0)
+            || (bits != 0
+                && self
+                    .0
+                    .get(limbs)
+                    .is_some_and(|&limb| limb << (32 - bits) != 0))
+    }
+
+    fn cmp(&self, other: &Big) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+
+    /// `self -= other`, `other` must not be larger
+    fn sub(&mut self, other: &Big) {
+        let mut borrow = false;
+        for i in 0..self.0.len() {
+            let rhs = other.0.get(i).copied().unwrap_or(0);
+            let (diff, o1) = self.0[i].overflowing_sub(rhs);
+            let (diff, o2) = diff.overflowing_sub(borrow as u32);
+            self.0[i] = diff;
+            borrow = o1 || o2;
+        }
+        self.trim();
+    }
+
+    /// Decimal digits (values 0-9), most significant first
+    fn to_digits(&self) -> Vec<u8> {
+        let mut limbs = self.0.clone();
+        let mut chunks = Vec::new();
+        while !limbs.is_empty() {
+            // Divide by 10^9 from the top, keeping the remainder
+            let mut rem = 0u64;
+            for limb in limbs.iter_mut().rev() {
+                let cur = rem << 32 | *limb as u64;
+                *limb = (cur / 1_000_000_000) as u32;
+                rem = cur % 1_000_000_000;
+            }
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+            chunks.push(rem as u32);
+        }
+
+        let mut digits = Vec::new();
+        for chunk in chunks.iter().rev() {
+            for i in (0..9).rev() {
+                digits.push((chunk / 10u32.pow(i) % 10) as u8);
+            }
+        }
+        digits
+    }
+}
+
+impl fmt::Display for Decimal {
+    /// Shortest literal with the exact value, e.g. `-125e-2`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negative {
+            f.write_str("-")?;
+        }
+        if self.digits.is_empty() {
+            return f.write_str("0");
+        }
+        for &digit in &self.digits {
+            write!(f, "{}", digit)?;
+        }
+        if self.exponent != 0 {
+            write!(f, "e{}", self.exponent)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ties, subnormals and overflow, which the standard library also rounds
+    /// correctly
+    #[test]
+    fn rounding_matches_std() {
+        for literal in [
+            "9007199254740993", // 2^53 + 1, a tie rounding to even
+            "9007199254740995", // 2^53 + 3, a tie rounding up
+            "9007199254740993.000000000000000000001",
+            "2.2250738585072011e-308", // Largest subnormal
+            "2.2250738585072014e-308", // Smallest normal
+            "2.4703282292062327e-324", // Half the smallest subnormal, to zero
+            "2.4703282292062328e-324", // Just above, to the smallest subnormal
+            "1.7976931348623157e308",  // Largest finite
+            "1.7976931348623158e308",  // Below the midpoint to infinity
+            "1.7976931348623159e308",  // Above it, to infinity
+            "-1e400",
+            "1e-400",
+            "3.4028235677973366e38", // Midpoint past the largest f32
+            "1.4012984643e-45",      // Smallest f32 subnormal
+            "7.006492321624085e-46", // Half of it, a tie
+            "16777217",              // 2^24 + 1, a tie for f32
+        ] {
+            let value = Decimal::parse(literal.as_bytes()).unwrap();
+            let f64_bits = literal.parse::<f64>().unwrap().to_bits();
+            let f32_bits = literal.parse::<f32>().unwrap().to_bits();
+            assert_eq!(value.to_f64_bits(), f64_bits, "{}", literal);
+            assert_eq!(value.to_f32_bits(), f32_bits, "{}", literal);
+        }
+    }
+}
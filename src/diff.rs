@@ -0,0 +1,142 @@
+//! Differential testing of several implementations
+//!
+//! Every input goes to each implementation, a Rust closure or an external
+//! program, and their verdicts are compared: either all of them accept the
+//! input with the same output or all of them reject it. Outputs that are
+//! JSON are compared in a canonical form, so implementations serializing the
+//! same document with different whitespace, escapes or number spellings
+//! agree.
+//!
+//! ```ignore
+//! let mut differential = Differential::new();
+//! differential.add_fn("mine", |input| my_parser::parse(input).ok().map(|doc| doc.to_json()));
+//! differential.add_program("python", Target::new(python_argv));
+//! let report = Harness::default().diff(&mut differential)?;
+//! ```
+use std::io;
+
+use crate::exec::{Execution, Target};
+use crate::harness::{catch, Outcome};
+use crate::value::Value;
+
+/// What an implementation made of an input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Accepted, with the normalized output
+    Accept(Vec<u8>),
+    Reject,
+
+    /// Crashed or hung, as described
+    Fail(Outcome),
+}
+
+type Implementation<'a> = Box<dyn FnMut(&[u8]) -> io::Result<Verdict> + 'a>;
+
+/// Implementations compared on every input
+#[derive(Default)]
+pub struct Differential<'a> {
+    names: Vec<String>,
+    implementations: Vec<Implementation<'a>>,
+}
+
+impl<'a> Differential<'a> {
+    pub fn new() -> Differential<'a> {
+        Differential::default()
+    }
+
+    /// Compare `f`, which returns the output for inputs it accepts and
+    /// `None` for the ones it rejects. Panics count as crashes.
+    pub fn add_fn<F>(&mut self, name: &str, mut f: F)
+    where
+        F: FnMut(&[u8]) -> Option<Vec<u8>> + 'a,
+    {
+        self.add(name, move |input| {
+            Ok(match catch(|| f(input)) {
+                Ok(Some(output)) => Verdict::Accept(normalize(&output)),
+                Ok(None) => Verdict::Reject,
+                Err(crash) => Verdict::Fail(Outcome::Crash(crash)),
+            })
+        });
+    }
+
+    /// Compare the program `target`, which accepts an input by exiting with
+    /// code 0 and its standard output
+    pub fn add_program(&mut self, name: &str, mut target: Target) {
+        target.capture();
+        self.add(name, move |input| {
+            Ok(match target.execute(input)? {
+                Execution::Exited(status) => match target.classify(status) {
+                    Outcome::Pass if status.success() => {
+                        Verdict::Accept(normalize(&target.stdout()?))
+                    }
+                    Outcome::Pass => Verdict::Reject,
+                    outcome => Verdict::Fail(outcome),
+                },
+                Execution::TimedOut => Verdict::Fail(Outcome::Hang(format!(
+                    "timed out after {:?}",
+                    target.timeout
+                ))),
+                Execution::Skipped => Verdict::Fail(Outcome::Skip),
+            })
+        });
+    }
+
+    fn add(&mut self, name: &str, implementation: impl FnMut(&[u8]) -> io::Result<Verdict> + 'a) {
+        self.names.push(name.to_string());
+        self.implementations.push(Box::new(implementation));
+    }
+
+    /// Run every implementation on `input` and compare their verdicts
+    pub fn run(&mut self, input: &[u8]) -> io::Result<Outcome> {
+        let mut verdicts = Vec::with_capacity(self.implementations.len());
+        for (name, implementation) in self.names.iter().zip(&mut self.implementations) {
+            match implementation(input)? {
+                // A crash or hang matters more than the disagreement it causes
+                Verdict::Fail(Outcome::Crash(mut crash)) => {
+                    // The same bug in different implementations is a
+                    // different bug
+                    crash.kind = format!("{} {}", name, crash.kind);
+                    crash.message = format!("{}: {}", name, crash.message);
+                    return Ok(Outcome::Crash(crash));
+                }
+                Verdict::Fail(Outcome::Hang(msg)) => {
+                    return Ok(Outcome::Hang(format!("{}: {}", name, msg)))
+                }
+                Verdict::Fail(outcome) => return Ok(outcome),
+                verdict => verdicts.push(verdict),
+            }
+        }
+
+        let first = match verdicts.first() {
+            Some(first) => first,
+            None => return Ok(Outcome::Pass),
+        };
+        let odd = match verdicts.iter().position(|verdict| verdict != first) {
+            Some(odd) => odd,
+            None => return Ok(Outcome::Pass),
+        };
+        let describe = |i: usize| match &verdicts[i] {
+            Verdict::Accept(_) => format!("{} accepts", self.names[i]),
+            _ => format!("{} rejects", self.names[i]),
+        };
+        Ok(Outcome::Mismatch(match (first, &verdicts[odd]) {
+            (Verdict::Accept(_), Verdict::Accept(_)) => {
+                format!("{} and {} differ in output", self.names[0], self.names[odd])
+            }
+            _ => format!("{}, {}", describe(0), describe(odd)),
+        }))
+    }
+}
+
+/// Canonical form of `output`: its compact serialization if it is a JSON
+/// document, otherwise the bytes without surrounding whitespace
+pub fn normalize(output: &[u8]) -> Vec<u8> {
+    match Value::parse(output) {
+        Ok(value) => {
+            let mut buf = Vec::new();
+            value.write(&mut buf);
+            buf
+        }
+        Err(_) => output.trim_ascii().to_vec(),
+    }
+}
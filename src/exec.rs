@@ -0,0 +1,245 @@
+//! Running an external program on each input
+//!
+//! The input reaches the program the way AFL passes it: on stdin by default,
+//! through a file whose path replaces every `@@` in the arguments, or in an
+//! environment variable. Runs that end in a signal or one of the configured
+//! exit codes are crashes, runs that outlive the timeout are killed and
+//! count as hangs. The output of the program can be kept for comparisons.
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::harness::Outcome;
+use crate::triage::Crash;
+
+/// Timeout of a single run by default
+pub const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How the program receives the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    Stdin,
+
+    /// In a file, its path substituted for `@@` in the arguments
+    File,
+
+    /// In the named environment variable. Inputs with NUL bytes can't be
+    /// passed this way and are skipped.
+    Env(String),
+}
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Execution {
+    Exited(ExitStatus),
+    TimedOut,
+
+    /// The input couldn't be passed to the program
+    Skipped,
+}
+
+/// External program under test
+#[derive(Debug)]
+pub struct Target {
+    /// Program and arguments
+    argv: Vec<String>,
+    pub delivery: Delivery,
+    pub timeout: Duration,
+
+    /// Exit codes that count as crashes, besides deaths by signal
+    pub crash_codes: Vec<i32>,
+
+    /// File holding the current input for `Stdin` and `File`
+    file: PathBuf,
+
+    /// File receiving the standard output, if it is kept
+    output: Option<PathBuf>,
+
+    /// File receiving the standard error, for sanitizer reports
+    errors: PathBuf,
+}
+
+impl Target {
+    /// Target running `argv`, fed through a file if an argument contains
+    /// `@@` and on stdin otherwise
+    pub fn new(argv: Vec<String>) -> Target {
+        assert!(!argv.is_empty());
+        let delivery = match argv[1..].iter().any(|arg| arg.contains("@@")) {
+            true => Delivery::File,
+            false => Delivery::Stdin,
+        };
+        Target {
+            argv,
+            delivery,
+            timeout: TIMEOUT,
+            crash_codes: Vec::new(),
+            file: temp_file("input"),
+            output: None,
+            errors: temp_file("errors"),
+        }
+    }
+
+    /// Keep the standard output of every run for `stdout`
+    pub fn capture(&mut self) {
+        self.output = Some(temp_file("output"));
+    }
+
+    /// Standard output of the last run, empty unless `capture` was called
+    pub fn stdout(&self) -> io::Result<Vec<u8>> {
+        match &self.output {
+            Some(output) => fs::read(output),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Run the program once on `input`
+    pub fn run(&mut self, input: &[u8]) -> io::Result<Outcome> {
+        Ok(match self.execute(input)? {
+            Execution::Exited(status) => self.classify(status),
+            Execution::TimedOut => Outcome::Hang(format!("timed out after {:?}", self.timeout)),
+            Execution::Skipped => Outcome::Skip,
+        })
+    }
+
+    /// Run the program once on `input`, without judging how it went
+    pub fn execute(&mut self, input: &[u8]) -> io::Result<Execution> {
+        let file = self.file.to_string_lossy();
+        let mut command = Command::new(&self.argv[0]);
+        for arg in &self.argv[1..] {
+            match self.delivery {
+                Delivery::File => command.arg(arg.replace("@@", &file)),
+                _ => command.arg(arg),
+            };
+        }
+        match &self.output {
+            Some(output) => command.stdout(File::create(output)?),
+            None => command.stdout(Stdio::null()),
+        };
+        command.stderr(File::create(&self.errors)?);
+
+        // Make sanitizers report through a signal, like AFL does, unless the
+        // user configured them
+        for (var, options) in SANITIZER_OPTIONS {
+            if std::env::var_os(var).is_none() {
+                command.env(var, options);
+            }
+        }
+
+        match &self.delivery {
+            Delivery::Stdin => {
+                fs::write(&self.file, input)?;
+                command.stdin(File::open(&self.file)?);
+            }
+            Delivery::File => {
+                fs::write(&self.file, input)?;
+                command.stdin(Stdio::null());
+            }
+            Delivery::Env(var) => {
+                if input.contains(&0) {
+                    return Ok(Execution::Skipped);
+                }
+                command.env(var, OsStr::from_bytes(input));
+                command.stdin(Stdio::null());
+            }
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", self.argv[0], err)))?;
+        Ok(match wait(&mut child, self.timeout)? {
+            Some(status) => Execution::Exited(status),
+            None => {
+                // Already gone if it exited right after the deadline
+                let _ = child.kill();
+                child.wait()?;
+                Execution::TimedOut
+            }
+        })
+    }
+
+    /// Crash if the program died from a signal or with one of the crash
+    /// codes, pass otherwise
+    pub fn classify(&self, status: ExitStatus) -> Outcome {
+        let (kind, message) = match (status.signal(), status.code()) {
+            (Some(signal), _) => {
+                let name = signal_name(signal);
+                let message = format!("killed by signal {} ({})", signal, name);
+                (name, message)
+            }
+            (_, Some(code)) if self.crash_codes.contains(&code) => {
+                (format!("exit:{}", code), format!("exit code {}", code))
+            }
+            _ => return Outcome::Pass,
+        };
+        // Without its report the crash still counts, only less precisely
+        let stderr = fs::read(&self.errors).unwrap_or_default();
+        Outcome::Crash(Crash::from_report(kind, message, &stderr))
+    }
+}
+
+impl Drop for Target {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.file);
+        let _ = fs::remove_file(&self.errors);
+        if let Some(output) = &self.output {
+            let _ = fs::remove_file(output);
+        }
+    }
+}
+
+/// Path of a new temporary file, distinct for every target of the process
+fn temp_file(kind: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("bnf-{}-{}.{}", std::process::id(), id, kind))
+}
+
+const SANITIZER_OPTIONS: [(&str, &str); 2] = [
+    ("ASAN_OPTIONS", "abort_on_error=1"),
+    (
+        "UBSAN_OPTIONS",
+        "halt_on_error=1:abort_on_error=1:print_stacktrace=1",
+    ),
+];
+
+/// Exit status of `child`, or `None` if it still runs after `timeout`
+fn wait(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+
+    // Poll often at first since most runs are short, then back off
+    let mut pause = Duration::from_micros(10);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(pause.min(deadline - now));
+        pause = (pause * 2).min(Duration::from_millis(5));
+    }
+}
+
+/// Name of `signal`, such as `SIGSEGV`, or `signal 42` for rarer ones
+fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
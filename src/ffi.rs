@@ -0,0 +1,87 @@
+//! C API
+//!
+//! With the `cdylib` feature the shared library exports functions to
+//! generate inputs from C and C++ harnesses, declared in `capi/include/bnf.h`:
+//!
+//! ```text
+//! struct grammar *grammar = grammar_new("Json");
+//! size_t size = grammar_generate(grammar, seed, buf, sizeof buf);
+//! grammar_free(grammar);
+//! ```
+//!
+//! A seed always gives the same input, the same as `bnf --replay` gives for
+//! it with the default limits.
+use core::ffi::{c_char, CStr};
+
+use crate::Generator;
+
+/// Rule generating the inputs, opaque to C
+pub struct Grammar {
+    generator: Generator,
+
+    /// Input being generated, reused across calls
+    buf: Vec<u8>,
+}
+
+/// Grammar generating inputs from the rule called `rule`, `Json` if null,
+/// or null if there is no such rule
+///
+/// # Safety
+///
+/// `rule` must be null or a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn grammar_new(rule: *const c_char) -> *mut Grammar {
+    let name = match rule.is_null() {
+        true => "Json",
+        false => match CStr::from_ptr(rule).to_str() {
+            Ok(name) => name,
+            Err(_) => return core::ptr::null_mut(),
+        },
+    };
+    match Generator::new().rule(name) {
+        Ok(generator) => Box::into_raw(Box::new(Grammar {
+            generator,
+            buf: Vec::new(),
+        })),
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+/// Generate the input for `seed`, copying up to `cap` bytes of it to `buf`
+/// and returning its full size, like `snprintf`. The input was cut short if
+/// that is more than `cap`.
+///
+/// # Safety
+///
+/// `grammar` must come from `grammar_new` and `buf` must be valid for
+/// writes of `cap` bytes. `buf` may be null if `cap` is 0, to query the
+/// size.
+#[no_mangle]
+pub unsafe extern "C" fn grammar_generate(
+    grammar: *mut Grammar,
+    seed: u64,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let grammar = &mut *grammar;
+    grammar.buf.clear();
+    grammar.generator.generate_seed(seed, &mut grammar.buf);
+    let size = grammar.buf.len().min(cap);
+    if size > 0 {
+        core::ptr::copy_nonoverlapping(grammar.buf.as_ptr(), buf, size);
+    }
+    grammar.buf.len()
+}
+
+/// Free `grammar`
+///
+/// # Safety
+///
+/// `grammar` must be null or come from `grammar_new`, and isn't valid
+/// anymore.
+#[no_mangle]
+pub unsafe extern "C" fn grammar_free(grammar: *mut Grammar) {
+    if !grammar.is_null() {
+        drop(Box::from_raw(grammar));
+    }
+}
@@ -0,0 +1,432 @@
+//! Allocation-free generation into a fixed-size buffer
+//!
+//! `generate` writes a `Json` document into a slice provided by the caller,
+//! such as the input page of a snapshot fuzzer, following the rules of
+//! `json4` without allocating. The document always fits and is always valid
+//! JSON: room for the closing `"`, `]` or `}` is set aside as soon as one is
+//! opened, and a value only starts once its shortest form fits, so the
+//! document winds down as the buffer fills up.
+//!
+//! ```
+//! let mut page = [0; 4096];
+//! let size = bnf::fixed::generate(&mut bnf::Rng::seeded(42), &bnf::Config::default(), &mut page);
+//! assert!(size <= page.len());
+//! ```
+use crate::json4::{JsonObject, KeyMode};
+use crate::value::same_string;
+use crate::{Config, Rng};
+
+/// Write a `Json` document to the start of `out`, returning its size
+///
+/// Any buffer of at least one byte fits a document. An empty `out` gets
+/// none, and 0 is returned.
+pub fn generate(rng: &mut Rng, config: &Config, out: &mut [u8]) -> usize {
+    let mut writer = Writer {
+        buf: out,
+        len: 0,
+        reserved: 0,
+        depth: 0,
+    };
+    if writer.room() > 0 {
+        writer.value(rng, config);
+    }
+    writer.len
+}
+
+/// `[0-9a-fA-F]`, as in `json4::Hex`
+const HEX: [u8; 22] = *b"0123456789abcdefABCDEF";
+
+/// Shortest forms of the `JsonValue` alternatives, in grammar order
+const SHORTEST: [usize; 7] = [2, 1, 2, 2, 4, 5, 4];
+
+/// Document being written to a buffer
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+
+    /// Bytes set aside for what has to follow, such as closing brackets
+    reserved: usize,
+
+    /// Rules entered so far, as the `depth` of `Generate`
+    depth: u64,
+}
+
+impl Writer<'_> {
+    /// Bytes left for anything else than what is set aside
+    fn room(&self) -> usize {
+        self.buf.len() - self.len - self.reserved
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.buf[self.len] = byte;
+        self.len += 1;
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    /// `JsonValue`, needing one byte of room
+    fn value(&mut self, rng: &mut Rng, config: &Config) {
+        self.depth += 1;
+        if self.depth >= config.max_depth {
+            return self.number(rng, config);
+        }
+        let choice = rng.weighted(&config.value_weights);
+
+        // A number always fits
+        if SHORTEST[choice] > self.room() {
+            return self.number(rng, config);
+        }
+        match choice {
+            0 => self.string(rng, config),
+            1 => self.number(rng, config),
+            2 => self.object(rng, config),
+            3 => self.array(rng, config),
+            4 => self.extend(b"true"),
+            5 => self.extend(b"false"),
+            6 => self.extend(b"null"),
+            _ => unreachable!(),
+        }
+    }
+
+    /// `Number`, needing one byte of room
+    fn number(&mut self, rng: &mut Rng, config: &Config) {
+        self.depth += 1;
+        // '-'? INT ('.' [0-9] +)? EXP?
+
+        // '-'?
+        if rng.next() % 2 == 1 && self.room() >= 2 {
+            self.push(b'-');
+        }
+
+        // INT
+        self.int(rng, config);
+
+        // ('.' [0-9]+)?
+        if rng.next() % 2 == 1 && self.room() >= 2 {
+            self.push(b'.');
+            let count = 1 + rng.next() % config.max_repeat;
+            self.digits(rng, count);
+        }
+
+        // EXP?
+        if rng.next() % 2 == 1 && self.room() >= 2 {
+            self.exp(rng, config);
+        }
+    }
+
+    /// `Int`, needing one byte of room
+    fn int(&mut self, rng: &mut Rng, config: &Config) {
+        self.depth += 1;
+        // '0' | [1-9] [0-9]*
+        match rng.next() % 2 {
+            0 => self.push(b'0'),
+            _ => {
+                self.push(b'1' + (rng.next() % 9) as u8);
+                let count = rng.next() % config.max_repeat;
+                self.digits(rng, count);
+            }
+        }
+    }
+
+    /// `Exp`, needing two bytes of room
+    fn exp(&mut self, rng: &mut Rng, config: &Config) {
+        self.depth += 1;
+        // [Ee] [+\-]? INT
+        self.push(if rng.next() % 2 == 1 { b'E' } else { b'e' });
+        if rng.next() % 2 == 1 && self.room() >= 2 {
+            self.push(if rng.next() % 2 == 1 { b'-' } else { b'+' });
+        }
+        self.int(rng, config);
+    }
+
+    /// Up to `count` digits, as many as fit
+    fn digits(&mut self, rng: &mut Rng, count: usize) {
+        for _ in 0..count.min(self.room()) {
+            self.push(b'0' + (rng.next() % 10) as u8);
+        }
+    }
+
+    /// `JsonString`, needing two bytes of room
+    fn string(&mut self, rng: &mut Rng, config: &Config) {
+        self.depth += 1;
+        self.push(b'"');
+        self.reserved += 1;
+        if self.depth <= config.max_depth {
+            for _ in 0..(rng.next() % config.max_repeat) {
+                let mut char = [0; 6];
+                let char = match rng.next() % 2 {
+                    0 => escape(rng, &mut char),
+                    _ => safe_code_point(rng, &mut char),
+                };
+                if char.len() > self.room() {
+                    break;
+                }
+                self.extend(char);
+            }
+        }
+        self.reserved -= 1;
+        self.push(b'"');
+    }
+
+    /// `JsonArray`, needing two bytes of room
+    fn array(&mut self, rng: &mut Rng, config: &Config) {
+        self.depth += 1;
+        self.push(b'[');
+        self.reserved += 1;
+        match rng.next() % 10 {
+            // '[' ']'
+            0 => {}
+
+            // '[' value (',' value)* ']'
+            _ if self.room() >= 1 => {
+                self.value(rng, config);
+                if self.depth <= config.max_depth {
+                    for _ in 0..(rng.next() % config.max_repeat) {
+                        if self.room() < 2 {
+                            break;
+                        }
+                        self.push(b',');
+                        self.value(rng, config);
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.reserved -= 1;
+        self.push(b']');
+    }
+
+    /// `JsonObject`, needing two bytes of room
+    fn object(&mut self, rng: &mut Rng, config: &Config) {
+        self.depth += 1;
+        self.push(b'{');
+        self.reserved += 1;
+        let start = self.len;
+        match rng.next() % 10 {
+            // '{' '}'
+            0 => {}
+
+            // '{' pair (',' pair)* '}'
+            _ if self.pair(rng, config, start) && self.depth <= config.max_depth => {
+                for _ in 0..(rng.next() % config.max_repeat) {
+                    if self.room() < 2 {
+                        break;
+                    }
+                    self.push(b',');
+                    if !self.pair(rng, config, start) {
+                        // No room or no usable key left, close the object early
+                        self.len -= 1;
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.reserved -= 1;
+        self.push(b'}');
+    }
+
+    /// `JsonPair` of the object whose pairs start at `start`, with the key
+    /// chosen according to `config.key_mode`
+    ///
+    /// Returns `false`, leaving the output untouched, if the pair doesn't fit
+    /// or no fitting key was found.
+    fn pair(&mut self, rng: &mut Rng, config: &Config, start: usize) -> bool {
+        self.depth += 1;
+        // The shortest pair, `"":0`
+        if self.room() < 4 {
+            return false;
+        }
+        let pair = self.len;
+        let duplicate = match config.key_mode {
+            KeyMode::Duplicate(percent) if pair > start && rng.next() % 100 < percent => {
+                let keys = Keys::new(&self.buf[start..pair]);
+                let count = keys.clone().count();
+                keys.clone().nth(rng.next() % count)
+            }
+            _ => None,
+        };
+
+        // Room for the ':' and the shortest value
+        self.reserved += 2;
+        let found = match duplicate {
+            // The key fit when it was first written, but maybe not anymore
+            Some((key_start, key_end)) if key_end - key_start <= self.room() => {
+                self.buf
+                    .copy_within(start + key_start..start + key_end, self.len);
+                self.len += key_end - key_start;
+                true
+            }
+            _ => match config.key_mode {
+                KeyMode::Unique => self.unique_key(rng, config, start),
+                _ => {
+                    self.string(rng, config);
+                    true
+                }
+            },
+        };
+        self.reserved -= 2;
+        if !found {
+            return false;
+        }
+        self.push(b':');
+        self.value(rng, config);
+        true
+    }
+
+    /// Key different from the earlier ones, once decoded, of the object
+    /// whose pairs start at `start`, `false` if none was found
+    fn unique_key(&mut self, rng: &mut Rng, config: &Config, start: usize) -> bool {
+        let key = self.len;
+        for _ in 0..JsonObject::KEY_ATTEMPTS {
+            self.string(rng, config);
+            let pairs = &self.buf[start..key];
+            let new = &self.buf[key..self.len];
+            if !Keys::new(pairs).any(|(s, e)| same_string(&pairs[s..e], new)) {
+                return true;
+            }
+
+            // Past `max_depth` every key is "", so the retries have to be bounded
+            self.len = key;
+        }
+        false
+    }
+}
+
+/// `Escape` written to `buf`
+fn escape<'a>(rng: &mut Rng, buf: &'a mut [u8; 6]) -> &'a [u8] {
+    // '\\' (["\\/bfnrt] | UNICODE)
+    buf[0] = b'\\';
+    match rng.next() % 2 {
+        0 => {
+            buf[1] = b'u';
+            for hex in &mut buf[2..] {
+                *hex = HEX[rng.next() % HEX.len()];
+            }
+            &buf[..]
+        }
+        _ => {
+            let values = [b'"', b'\\', b'/', b'b', b'f', b'n', b'r', b't'];
+            buf[1] = values[rng.next() % values.len()];
+            &buf[..2]
+        }
+    }
+}
+
+/// `SafeCodePoint` written to `buf`
+fn safe_code_point<'a>(rng: &mut Rng, buf: &'a mut [u8; 6]) -> &'a [u8] {
+    loop {
+        let num = (rng.next() % 0x10ffff) as u32;
+
+        // Ignore \u0000..\u001f and " and \
+        if num < 0x20 || num == (b'"' as u32) || num == (b'\\' as u32) {
+            continue;
+        }
+
+        // Surrogates aren't characters and can't be encoded as UTF-8
+        if let Some(c) = core::char::from_u32(num) {
+            return c.encode_utf8(&mut buf[..4]).as_bytes();
+        }
+    }
+}
+
+/// Byte ranges of the keys, quotes included, of the pairs `"key":value,...`
+/// of an object, which may end with a `,`
+#[derive(Clone)]
+struct Keys<'a> {
+    pairs: &'a [u8],
+    pos: usize,
+}
+
+impl Keys<'_> {
+    fn new(pairs: &[u8]) -> Keys<'_> {
+        Keys { pairs, pos: 0 }
+    }
+}
+
+impl Iterator for Keys<'_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        if self.pos >= self.pairs.len() {
+            return None;
+        }
+        let key = self.pos;
+        let end = string_end(self.pairs, key);
+
+        // Skip the ':', the value and the ','
+        let mut pos = end + 1;
+        let mut nesting = 0;
+        while pos < self.pairs.len() {
+            match self.pairs[pos] {
+                b'"' => {
+                    pos = string_end(self.pairs, pos);
+                    continue;
+                }
+                b'[' | b'{' => nesting += 1,
+                b']' | b'}' => nesting -= 1,
+                b',' if nesting == 0 => {
+                    pos += 1;
+                    break;
+                }
+                _ => {}
+            }
+            pos += 1;
+        }
+        self.pos = pos;
+        Some((key, end))
+    }
+}
+
+/// Index just past the string starting at `start`
+fn string_end(buf: &[u8], start: usize) -> usize {
+    let mut pos = start + 1;
+    loop {
+        match buf[pos] {
+            b'\\' => pos += 2,
+            b'"' => return pos + 1,
+            _ => pos += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    /// Whether an object has the same key twice
+    fn duplicate(value: &Value) -> bool {
+        match value {
+            Value::Array(values) => values.iter().any(duplicate),
+            Value::Object(pairs) => pairs.iter().enumerate().any(|(i, (key, value))| {
+                pairs[..i].iter().any(|(other, _)| other == key) || duplicate(value)
+            }),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn always_fits_and_parses() {
+        for key_mode in [KeyMode::Random, KeyMode::Unique, KeyMode::Duplicate(40)] {
+            let config = Config {
+                key_mode,
+                ..Config::default()
+            };
+            for size in [1, 2, 3, 5, 8, 13, 64, 512, 4096] {
+                for seed in 0..200 {
+                    let mut page = vec![0; size];
+                    let len = generate(&mut Rng::seeded(seed), &config, &mut page);
+                    assert!(len <= size);
+                    let value = Value::parse(&page[..len]).expect("invalid document");
+                    if let KeyMode::Unique = key_mode {
+                        assert!(!duplicate(&value), "duplicate key");
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Float parsing accuracy oracle for `Number`
+//!
+//! Generates number literals together with the bit patterns a correct
+//! `f64`/`f32` parser has to produce for them, computed exactly by
+//! `Decimal`. Next to ordinary `json4` numbers it deliberately produces the
+//! cases float parsers get wrong: exact halfway points between two
+//! neighbouring floats and literals a hair above or below them, long
+//! mantissas, and the overflow and underflow boundaries.
+//!
+//! The bits are checked against the standard library, which rounds
+//! correctly, so a regression in `Decimal` shows up in the tests.
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::decimal::{Decimal, Format, F32, F64};
+use crate::json4::Number;
+use crate::{Config, Generate, Rng};
+
+/// Correctly rounded results for a number literal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Float {
+    pub f64_bits: u64,
+    pub f32_bits: u32,
+}
+
+/// Rules that also return the correctly rounded floats of what they generated
+pub trait GenerateFloat {
+    fn generate_float(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut Vec<u8>) -> Float;
+}
+
+/// Longest mantissa generated, well past the 767 significant digits that can
+/// matter when rounding to `f64`
+const MAX_DIGITS: usize = 800;
+
+impl GenerateFloat for Number {
+    fn generate_float(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut Vec<u8>) -> Float {
+        *depth += 1;
+        let start = buf.len();
+        let value = match rng.next() % 8 {
+            0 | 1 => None,
+            2 => Some(halfway(rng, &F64)),
+            3 => Some(halfway(rng, &F32)),
+            4 => {
+                let format = if rng.next() % 2 == 1 { &F64 } else { &F32 };
+                Some(near_halfway(rng, config, format))
+            }
+            5 => Some(long_mantissa(rng)),
+            6 => Some(boundary(rng)),
+            // Exact value of a random double, as long as it takes
+            7 => Some(exact(&F64, F64.random_bits(rng))),
+            _ => unreachable!(),
+        };
+        match value {
+            Some(value) => literal(rng, config, &value, buf),
+            None => Number::generate(rng, config, depth, buf),
+        }
+
+        // Parse what was written, so the bits match the literal by construction
+        let value = Decimal::parse(&buf[start..]).unwrap();
+        Float {
+            f64_bits: value.to_f64_bits(),
+            f32_bits: value.to_f32_bits(),
+        }
+    }
+}
+
+/// Exact value of the float with `bits`
+fn exact(format: &Format, bits: u64) -> Decimal {
+    let (negative, mantissa, exp2) = format.decompose(bits);
+    Decimal::from_dyadic(negative, mantissa, exp2)
+}
+
+/// Exact midpoint between the float with `bits` and the next one away from
+/// zero
+fn midpoint(format: &Format, bits: u64) -> Decimal {
+    let (negative, mantissa, exp2) = format.decompose(bits);
+    Decimal::from_dyadic(negative, 2 * mantissa + 1, exp2 - 1)
+}
+
+/// Exact midpoint between a random float and the next one up
+///
+/// Includes the midpoint past the largest float, which rounds to infinity,
+/// and the one below the smallest subnormal, which rounds to zero.
+fn halfway(rng: &mut Rng, format: &Format) -> Decimal {
+    midpoint(format, format.random_bits(rng))
+}
+
+/// A midpoint moved by one unit in a digit far beyond the last one, so that
+/// only exact parsers see it isn't a tie
+fn near_halfway(rng: &mut Rng, config: &Config, format: &Format) -> Decimal {
+    let mid = halfway(rng, format);
+    let extra = 1 + rng.next() % config.max_repeat;
+    nudge(&mid, extra, rng.next() % 2 == 1)
+}
+
+/// `mid` moved away from zero if `up`, else towards it, by one unit
+/// `extra + 1` digits past its last one
+fn nudge(mid: &Decimal, extra: usize, up: bool) -> Decimal {
+    let mut digits = mid.digits.clone();
+    if up {
+        // Just above: append 00..01
+        digits.extend(core::iter::repeat_n(0, extra));
+        digits.push(1);
+    } else {
+        // Just below: decrement the last digit, which is never zero, and
+        // append 99..9
+        *digits.last_mut().unwrap() -= 1;
+        digits.extend(core::iter::repeat_n(9, extra + 1));
+    }
+    let exponent = mid.exponent - extra as i64 - 1;
+    Decimal::new(mid.negative, digits, exponent)
+}
+
+/// Random significant digits, more than any float holds, anywhere in the
+/// range of `f64`
+fn long_mantissa(rng: &mut Rng) -> Decimal {
+    let len = 20 + rng.next() % (MAX_DIGITS - 19);
+    let mut digits = vec![1 + (rng.next() % 9) as u8];
+    digits.extend((1..len).map(|_| (rng.next() % 10) as u8));
+
+    // Leading digit between 1e-330 and 1e310
+    let magnitude = (rng.next() % 641) as i64 - 330;
+    Decimal::new(rng.next() % 2 == 1, digits, magnitude - len as i64 + 1)
+}
+
+/// Bits of the smallest and largest subnormal, the smallest normal and the
+/// largest finite float
+fn extremes(format: &Format) -> [u64; 4] {
+    [
+        1,
+        (1 << format.mantissa_bits) - 1,
+        1 << format.mantissa_bits,
+        format.max_bits(),
+    ]
+}
+
+/// Extremes of both formats and the midpoints to their neighbours
+fn boundary(rng: &mut Rng) -> Decimal {
+    let format = if rng.next() % 2 == 1 { &F64 } else { &F32 };
+    let bits = extremes(format)[rng.next() % 4];
+    let negative = rng.next() % 2 == 1;
+    let mut value = match rng.next() % 3 {
+        0 => exact(format, bits),
+        1 => midpoint(format, bits),
+        // Positive floats are ordered like their bits
+        2 => midpoint(format, bits - 1),
+        _ => unreachable!(),
+    };
+    value.negative = negative;
+    value
+}
+
+/// Write `value` as a `Number` literal in a randomly chosen notation
+///
+/// Scientific (`1.25e-3`), integer mantissa (`125e-5`) or positional
+/// (`0.00125`), with random exponent case, `+` and trailing zeros.
+fn literal(rng: &mut Rng, config: &Config, value: &Decimal, buf: &mut Vec<u8>) {
+    let digit = |d: &u8| b'0' + d;
+    if value.negative {
+        buf.push(b'-');
+    }
+    if value.is_zero() {
+        return buf.push(b'0');
+    }
+
+    let digits = &value.digits;
+    let len = digits.len() as i64;
+    let exponent = match rng.next() % 3 {
+        // Scientific
+        0 => {
+            buf.push(digit(&digits[0]));
+            if len > 1 {
+                buf.push(b'.');
+                buf.extend(digits[1..].iter().map(digit));
+                trailing_zeros(rng, config, buf);
+            }
+            value.exponent + len - 1
+        }
+
+        // Positional, unless that gets huge
+        1 if value.exponent.abs() <= 64 => {
+            let point = len + value.exponent;
+            if value.exponent >= 0 {
+                buf.extend(digits.iter().map(digit));
+                buf.extend(core::iter::repeat_n(b'0', value.exponent as usize));
+            } else if point > 0 {
+                buf.extend(digits[..point as usize].iter().map(digit));
+                buf.push(b'.');
+                buf.extend(digits[point as usize..].iter().map(digit));
+                trailing_zeros(rng, config, buf);
+            } else {
+                buf.extend_from_slice(b"0.");
+                buf.extend(core::iter::repeat_n(b'0', -point as usize));
+                buf.extend(digits.iter().map(digit));
+                trailing_zeros(rng, config, buf);
+            }
+            0
+        }
+
+        // Integer mantissa
+        _ => {
+            buf.extend(digits.iter().map(digit));
+            value.exponent
+        }
+    };
+
+    if exponent != 0 || rng.next() % 4 == 1 {
+        buf.push(if rng.next() % 2 == 1 { b'e' } else { b'E' });
+        if exponent >= 0 && rng.next() % 2 == 1 {
+            buf.push(b'+');
+        }
+        buf.extend_from_slice(exponent.to_string().as_bytes());
+    }
+}
+
+/// Zeros after the last fraction digit, which don't change the value
+fn trailing_zeros(rng: &mut Rng, config: &Config, buf: &mut Vec<u8>) {
+    if rng.next() % 4 == 1 {
+        buf.extend(core::iter::repeat_n(
+            b'0',
+            1 + rng.next() % config.max_repeat,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bits of `value` rounded to `format`, checked against the standard
+    /// library
+    fn round(format: &Format, value: &Decimal) -> u64 {
+        let literal = value.to_string();
+        if format.mantissa_bits == F64.mantissa_bits {
+            let bits = value.to_f64_bits();
+            assert_eq!(
+                literal.parse::<f64>().unwrap().to_bits(),
+                bits,
+                "{}",
+                literal
+            );
+            bits
+        } else {
+            let bits = value.to_f32_bits();
+            assert_eq!(
+                literal.parse::<f32>().unwrap().to_bits(),
+                bits,
+                "{}",
+                literal
+            );
+            bits as u64
+        }
+    }
+
+    #[test]
+    fn matches_std() {
+        let mut rng = Rng::seeded(0);
+        for _ in 0..20_000 {
+            let mut buf = Vec::new();
+            let float = Number::generate_float(&mut rng, &Config::default(), &mut 0, &mut buf);
+            let literal = core::str::from_utf8(&buf).unwrap();
+            let f64_bits = literal.parse::<f64>().unwrap().to_bits();
+            let f32_bits = literal.parse::<f32>().unwrap().to_bits();
+            assert_eq!(float.f64_bits, f64_bits, "{}", literal);
+            assert_eq!(float.f32_bits, f32_bits, "{}", literal);
+        }
+    }
+
+    #[test]
+    fn halfway_ties_to_even() {
+        for (format, bits, expected) in [
+            (&F64, 0x3ff0000000000000, 0x3ff0000000000000),
+            (&F64, 0x3ff0000000000001, 0x3ff0000000000002),
+            (&F64, 0xbff0000000000001, 0xbff0000000000002),
+            (&F64, 0, 0),
+            (&F64, 0x7fefffffffffffff, 0x7ff0000000000000),
+            (&F32, 0x3f800000, 0x3f800000),
+            (&F32, 0x3f800001, 0x3f800002),
+            (&F32, 0, 0),
+            (&F32, 0x7f7fffff, 0x7f800000),
+        ] {
+            assert_eq!(
+                round(format, &midpoint(format, bits)),
+                expected,
+                "{:x}",
+                bits
+            );
+        }
+    }
+
+    #[test]
+    fn near_halfway_leaves_the_tie() {
+        for (format, bits, below, above) in [
+            (
+                &F64,
+                0x3ff0000000000000,
+                0x3ff0000000000000,
+                0x3ff0000000000001,
+            ),
+            (
+                &F64,
+                0x3ff0000000000001,
+                0x3ff0000000000001,
+                0x3ff0000000000002,
+            ),
+            (
+                &F64,
+                0x8000000000000000,
+                0x8000000000000000,
+                0x8000000000000001,
+            ),
+            (
+                &F64,
+                0x7fefffffffffffff,
+                0x7fefffffffffffff,
+                0x7ff0000000000000,
+            ),
+            (&F32, 0x3f800000, 0x3f800000, 0x3f800001),
+            (&F32, 0x00000001, 0x00000001, 0x00000002),
+        ] {
+            let mid = midpoint(format, bits);
+            for extra in [1, 20] {
+                assert_eq!(
+                    round(format, &nudge(&mid, extra, false)),
+                    below,
+                    "{:x}",
+                    bits
+                );
+                assert_eq!(
+                    round(format, &nudge(&mid, extra, true)),
+                    above,
+                    "{:x}",
+                    bits
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn boundary_neighbours() {
+        // Exact value, midpoint to the next float down and up
+        for (format, expected) in [
+            (
+                &F64,
+                [
+                    (1, 0, 2),
+                    (0x000fffffffffffff, 0x000ffffffffffffe, 0x0010000000000000),
+                    (0x0010000000000000, 0x0010000000000000, 0x0010000000000000),
+                    (0x7fefffffffffffff, 0x7feffffffffffffe, 0x7ff0000000000000),
+                ],
+            ),
+            (
+                &F32,
+                [
+                    (1, 0, 2),
+                    (0x007fffff, 0x007ffffe, 0x00800000),
+                    (0x00800000, 0x00800000, 0x00800000),
+                    (0x7f7fffff, 0x7f7ffffe, 0x7f800000),
+                ],
+            ),
+        ] {
+            for (&bits, (value, below, above)) in extremes(format).iter().zip(expected) {
+                assert_eq!(round(format, &exact(format, bits)), value);
+                assert_eq!(round(format, &midpoint(format, bits - 1)), below);
+                assert_eq!(round(format, &midpoint(format, bits)), above);
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Generation of inputs from a rule with runtime limits
+//!
+//! ```ignore
+//! let mut generator = Generator::new().rule("JsonArray")?.max_depth(32).seed(42);
+//! let mut buf = Vec::new();
+//! let seed = generator.generate(&mut buf);
+//! ```
+//!
+//! Every input has its own seed, derived from the master seed like in a run
+//! of `bnf`, which regenerates it with `Generator::generate_seed` or
+//! `bnf --replay`.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::json4::{self, Json, KeyMode};
+use crate::{input_seed, Config, Generate, GenerateFn, Rng};
+
+/// Generator of inputs, configured by chaining its setters
+#[derive(Clone)]
+pub struct Generator {
+    rule: GenerateFn,
+    config: Config,
+
+    /// Master seed of the inputs
+    seed: u64,
+
+    /// Sequence number of the next input
+    sequence: u64,
+}
+
+#[cfg(any(target_arch = "x86_64", feature = "std"))]
+impl Default for Generator {
+    fn default() -> Generator {
+        Generator::new()
+    }
+}
+
+impl Generator {
+    /// Generator of `Json` documents with the default limits, seeded from
+    /// the clock
+    #[cfg(any(target_arch = "x86_64", feature = "std"))]
+    pub fn new() -> Generator {
+        Generator::seeded(crate::clock_seed())
+    }
+
+    /// Generator of `Json` documents with the default limits and the master
+    /// seed `seed`, for targets without a clock
+    pub fn seeded(seed: u64) -> Generator {
+        Generator {
+            rule: Json::generate,
+            config: Config::default(),
+            seed,
+            sequence: 0,
+        }
+    }
+
+    /// Generate from the rule called `name`, an error if there is none
+    pub fn rule(self, name: &str) -> Result<Generator, String> {
+        match json4::rule(name) {
+            Some(rule) => Ok(self.rule_fn(rule)),
+            None => Err(format!("unknown rule {}", name)),
+        }
+    }
+
+    /// Generate from `rule`, such as `JsonArray::generate`
+    pub fn rule_fn(mut self, rule: GenerateFn) -> Generator {
+        self.rule = rule;
+        self
+    }
+
+    /// Depth past which rules stop recursing
+    pub fn max_depth(mut self, max_depth: u64) -> Generator {
+        self.config.max_depth = max_depth;
+        self
+    }
+
+    /// Upper bound (exclusive) on the repetitions of a `*` or `+`, an error
+    /// if 0
+    pub fn max_repeat(mut self, max_repeat: usize) -> Result<Generator, String> {
+        self.config.max_repeat = max_repeat;
+        self.checked()
+    }
+
+    /// Key policy of the objects
+    pub fn key_mode(mut self, key_mode: KeyMode) -> Generator {
+        self.config.key_mode = key_mode;
+        self
+    }
+
+    /// Relative weights of the `JsonValue` alternatives, in the order of
+    /// `json4::VALUE_NAMES`, an error if they are all zero
+    pub fn value_weights(mut self, value_weights: [u32; 7]) -> Result<Generator, String> {
+        self.config.value_weights = value_weights;
+        self.checked()
+    }
+
+    /// Every setting at once, an error if one is invalid as for its setter
+    pub fn config(mut self, config: Config) -> Result<Generator, String> {
+        self.config = config;
+        self.checked()
+    }
+
+    /// The generator if its settings can't make the rules panic
+    fn checked(self) -> Result<Generator, String> {
+        if self.config.max_repeat == 0 {
+            return Err("max_repeat needs to be at least 1".to_string());
+        }
+        if self.config.value_weights.iter().all(|&weight| weight == 0) {
+            return Err("weights can't all be zero".to_string());
+        }
+        Ok(self)
+    }
+
+    /// Master seed, restarting the sequence of inputs
+    pub fn seed(mut self, seed: u64) -> Generator {
+        self.seed = seed;
+        self.sequence = 0;
+        self
+    }
+
+    /// Master seed of the inputs
+    pub fn master_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Append the next input to `buf`, returning its seed
+    pub fn generate(&mut self, buf: &mut Vec<u8>) -> u64 {
+        let seed = input_seed(self.seed, self.sequence);
+        self.sequence += 1;
+        self.generate_seed(seed, buf);
+        seed
+    }
+
+    /// Append the input with seed `seed` to `buf`, returning the depth it
+    /// reached
+    pub fn generate_seed(&self, seed: u64, buf: &mut Vec<u8>) -> u64 {
+        let mut depth = 0;
+        (self.rule)(&mut Rng::seeded(seed), &self.config, &mut depth, buf);
+        depth
+    }
+}
@@ -0,0 +1,310 @@
+//! In-process fuzzing harness
+//!
+//! Feeds generated inputs straight to a Rust function and catches its
+//! panics, so parsers can be fuzzed without a separate fuzzer:
+//!
+//! ```ignore
+//! let report = harness::fuzz(|input: &[u8]| {
+//!     my_parser::parse(input).unwrap();
+//! })?;
+//! eprintln!("{:.0} execs/s", report.execs_per_sec());
+//! ```
+//!
+//! External programs are fuzzed the same way through `Harness::exec` and an
+//! `exec::Target`, and several implementations are compared with each other
+//! through `Harness::diff` and a `diff::Differential`.
+//!
+//! Inputs the target hangs on, or the implementations disagree on, are saved
+//! to the hang and mismatch directories, named `id:000000,seed:SEED` like a
+//! corpus, and the seed regenerates them with `bnf --replay`. Crashes are
+//! deduplicated, see `triage`.
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::corpus::{Corpus, Meta};
+use crate::diff::Differential;
+use crate::exec::Target;
+use crate::interrupt::interrupted;
+use crate::json4::Json;
+use crate::triage::{Buckets, Crash};
+use crate::{clock_seed, input_seed, Config, Generate, GenerateFn, Rng};
+
+/// Settings of a fuzzing run
+#[derive(Clone)]
+pub struct Harness {
+    /// Rule generating the inputs, `Json::generate` by default
+    pub rule: GenerateFn,
+
+    /// Name of `rule`, recorded with the crashes
+    pub rule_name: String,
+    pub config: Config,
+
+    /// Master seed, picked from the clock if `None`
+    pub seed: Option<u64>,
+
+    /// Stop after this many executions
+    pub count: Option<u64>,
+
+    /// Stop after this much time
+    pub time: Option<Duration>,
+
+    /// Directory receiving the inputs the target crashed on
+    pub crashes: PathBuf,
+
+    /// Directory receiving the inputs the target timed out on
+    pub hangs: PathBuf,
+
+    /// Directory receiving the inputs compared implementations disagree on
+    pub mismatches: PathBuf,
+
+    /// Report progress on stderr every second
+    pub stats: bool,
+}
+
+impl Default for Harness {
+    fn default() -> Harness {
+        Harness {
+            rule: Json::generate,
+            rule_name: "Json".to_string(),
+            config: Config::default(),
+            seed: None,
+            count: None,
+            time: None,
+            crashes: PathBuf::from("crashes"),
+            hangs: PathBuf::from("hangs"),
+            mismatches: PathBuf::from("mismatches"),
+            stats: false,
+        }
+    }
+}
+
+/// Result of running the target on one input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+
+    /// The target crashed
+    Crash(Crash),
+
+    /// The target didn't finish in time, as described
+    Hang(String),
+
+    /// Compared implementations disagreed, as described
+    Mismatch(String),
+
+    /// The input couldn't be passed to the target and wasn't run
+    Skip,
+}
+
+/// Outcome of a fuzzing run
+#[derive(Debug, Clone, Copy)]
+pub struct Report {
+    /// Master seed the inputs were generated from
+    pub seed: u64,
+    pub execs: u64,
+    pub crashes: u64,
+
+    /// Buckets of the crashes, see `triage`
+    pub unique_crashes: u64,
+    pub hangs: u64,
+    pub mismatches: u64,
+    pub elapsed: Duration,
+
+    /// Why the run stopped: `done` on a limit, `interrupted` on Ctrl-C
+    pub reason: &'static str,
+}
+
+impl Report {
+    pub fn execs_per_sec(&self) -> f64 {
+        self.execs as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Fuzz `target` with the default settings until Ctrl-C
+pub fn fuzz<F: FnMut(&[u8])>(target: F) -> io::Result<Report> {
+    Harness::default().fuzz(target)
+}
+
+impl Harness {
+    /// Call `target` on generated inputs until a limit is reached, saving
+    /// the inputs it panics on
+    pub fn fuzz<F: FnMut(&[u8])>(&self, mut target: F) -> io::Result<Report> {
+        self.run(|input| match catch(|| target(input)) {
+            Ok(()) => Ok(Outcome::Pass),
+            Err(crash) => Ok(Outcome::Crash(crash)),
+        })
+    }
+
+    /// Run the external program `target` on generated inputs until a limit
+    /// is reached, saving the inputs it crashes or hangs on
+    pub fn exec(&self, target: &mut Target) -> io::Result<Report> {
+        self.run(|input| target.run(input))
+    }
+
+    /// Run every implementation in `differential` on generated inputs until
+    /// a limit is reached, saving the inputs they disagree, crash or hang on
+    pub fn diff(&self, differential: &mut Differential) -> io::Result<Report> {
+        self.run(|input| differential.run(input))
+    }
+
+    /// Fuzzing loop around `exec`, which runs the target on an input
+    fn run(&self, exec: impl FnMut(&[u8]) -> io::Result<Outcome>) -> io::Result<Report> {
+        // Keep the panic messages for the reports instead of letting every
+        // crash print its own
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|info| {
+            let location = info
+                .location()
+                .map(|location| format!("{}:{}", location.file(), location.line()));
+            let crash = Crash::from_panic(info.to_string(), location, &Backtrace::force_capture());
+            PANIC.with(|panic| *panic.borrow_mut() = Some(crash));
+        }));
+        let res = self.run_loop(exec);
+        panic::set_hook(hook);
+        res
+    }
+
+    fn run_loop(&self, mut exec: impl FnMut(&[u8]) -> io::Result<Outcome>) -> io::Result<Report> {
+        let seed = self.seed.unwrap_or_else(clock_seed);
+        let mut crashes = None;
+        let mut hangs = None;
+        let mut mismatches = None;
+
+        let start = Instant::now();
+        let mut last_report = start;
+        let mut report = Report {
+            seed,
+            execs: 0,
+            crashes: 0,
+            unique_crashes: 0,
+            hangs: 0,
+            mismatches: 0,
+            elapsed: Duration::default(),
+            reason: "done",
+        };
+        let mut buf = Vec::new();
+        let mut sequence = 0;
+        loop {
+            if self.count.is_some_and(|count| report.execs >= count) {
+                break;
+            }
+            if interrupted() {
+                report.reason = "interrupted";
+                break;
+            }
+            let now = Instant::now();
+            if self.time.is_some_and(|time| now - start >= time) {
+                break;
+            }
+            if self.stats && now - last_report >= Duration::from_secs(1) {
+                let execs_per_sec = report.execs as f64 / (now - start).as_secs_f64();
+                eprintln!(
+                    "Time: {:10.2} s / {:10} execs / {:6} crashes ({} unique) / {:6} hangs / \
+                     {:6} mismatches = {:10.0} execs/s",
+                    (now - start).as_secs_f64(),
+                    report.execs,
+                    report.crashes,
+                    report.unique_crashes,
+                    report.hangs,
+                    report.mismatches,
+                    execs_per_sec
+                );
+                last_report = now;
+            }
+
+            let input_seed = input_seed(seed, sequence);
+            sequence += 1;
+            let mut depth = 0;
+            buf.clear();
+            (self.rule)(
+                &mut Rng::seeded(input_seed),
+                &self.config,
+                &mut depth,
+                &mut buf,
+            );
+
+            let outcome = exec(&buf)?;
+
+            // Ctrl-C reaches child processes too, don't blame the input
+            if interrupted() {
+                report.reason = "interrupted";
+                break;
+            }
+            let meta = Meta {
+                seed: input_seed,
+                grammar: "json",
+                rule: &self.rule_name,
+                mode: "valid",
+                depth,
+                max_depth: self.config.max_depth,
+                max_repeat: self.config.max_repeat,
+            };
+            let (dir, corpus, kind, msg) = match outcome {
+                Outcome::Pass => {
+                    report.execs += 1;
+                    continue;
+                }
+                Outcome::Skip => continue,
+                Outcome::Crash(crash) => {
+                    report.execs += 1;
+                    report.crashes += 1;
+                    if crashes.is_none() {
+                        crashes = Some(Buckets::open(&self.crashes)?);
+                    }
+                    let buckets = crashes.as_mut().unwrap();
+                    let new = buckets.save(&crash, &buf, &meta)?;
+                    report.unique_crashes = buckets.buckets() as u64;
+                    if new {
+                        eprintln!(
+                            "crash in new bucket {:016x} ({}) with seed {}: {}",
+                            crash.bucket(),
+                            crash.kind,
+                            input_seed,
+                            crash.message
+                        );
+                    }
+                    continue;
+                }
+                Outcome::Hang(msg) => {
+                    report.hangs += 1;
+                    (&self.hangs, &mut hangs, "hang", msg)
+                }
+                Outcome::Mismatch(msg) => {
+                    report.mismatches += 1;
+                    (&self.mismatches, &mut mismatches, "mismatch", msg)
+                }
+            };
+            report.execs += 1;
+
+            if corpus.is_none() {
+                *corpus = Some(Corpus::open(dir, true)?);
+            }
+            corpus.as_mut().unwrap().save(&buf, &meta)?;
+            eprintln!("{} with seed {}: {}", kind, input_seed, msg);
+        }
+
+        report.elapsed = start.elapsed();
+        Ok(report)
+    }
+}
+
+/// Run `f`, turning a panic into a crash
+///
+/// Stack traces are only captured while a harness runs, outside of one the
+/// crash is only described as a panic.
+pub fn catch<T>(f: impl FnOnce() -> T) -> Result<T, Crash> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|_| {
+        PANIC
+            .with(|panic| panic.borrow_mut().take())
+            .unwrap_or_else(|| Crash::from_panic("panic".to_string(), None, &Backtrace::disabled()))
+    })
+}
+
+thread_local! {
+    /// Last panic on this thread
+    static PANIC: RefCell<Option<Crash>> = const { RefCell::new(None) };
+}
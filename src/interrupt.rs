@@ -0,0 +1,33 @@
+//! Graceful shutdown on Ctrl-C
+//!
+//! The first SIGINT only sets a flag that generation and fuzzing loops poll,
+//! so they can stop at an input boundary and report what they did. A second
+//! one kills the process as usual.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the SIGINT handler to ask for a graceful shutdown
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+const SIGINT: i32 = 2;
+const SIG_DFL: usize = 0;
+
+extern "C" {
+    fn signal(signum: i32, handler: usize) -> usize;
+}
+
+extern "C" fn on_interrupt(_signum: i32) {
+    INTERRUPTED.store(true, Ordering::Release);
+
+    // A second Ctrl-C kills the process right away
+    unsafe { signal(SIGINT, SIG_DFL) };
+}
+
+/// Turn the first SIGINT into a graceful shutdown
+pub fn catch_interrupt() {
+    unsafe { signal(SIGINT, on_interrupt as extern "C" fn(i32) as usize) };
+}
+
+/// Whether a Ctrl-C asked for a shutdown since `catch_interrupt`
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Acquire)
+}
@@ -0,0 +1,295 @@
+//! Generation of the recursive rules of `json4` with an explicit stack
+//!
+//! In `json4` every nested value is a nested call (`JsonValue` ->
+//! `JsonArray` -> `JsonValue` ...), so a large `max_depth` overflows the
+//! native stack long before it runs out of memory. Here the values still to
+//! close are kept on a heap-allocated stack instead, which allows nesting
+//! millions of levels deep:
+//!
+//! ```
+//! let config = bnf::Config {
+//!     max_depth: 1_000_000,
+//!     ..bnf::Config::default()
+//! };
+//! let mut buf = Vec::new();
+//! bnf::iterative::generate(&mut bnf::Rng::seeded(42), &config, &mut 0, &mut buf);
+//! ```
+//!
+//! A seed gives the same input as the recursive rule with the same `Config`,
+//! and the same depth, so either can replay the inputs of the other.
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::json4::{self, JsonObject, JsonString, KeyMode, Number};
+use crate::{Config, Generate, GenerateFn, Rng, Sink};
+
+/// Rest of a rule left to generate once the values above it are done
+enum Frame {
+    /// `JsonValue`
+    Value,
+
+    /// Rest of a `JsonArray` after a value, with the number of values left
+    /// once drawn
+    Array { left: Option<usize> },
+
+    /// Rest of a `JsonObject` after a pair, with the number of pairs left
+    /// once drawn and the keys of the object as in `JsonObject::key`
+    Object {
+        left: Option<usize>,
+        keys: Vec<u8>,
+        ranges: Vec<(usize, usize)>,
+    },
+}
+
+/// Same as `json4::Json::generate`
+pub fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
+    *depth += 1;
+    run(rng, config, depth, buf, vec![Frame::Value]);
+}
+
+/// Same as `json4::JsonValue::generate`
+fn json_value<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
+    run(rng, config, depth, buf, vec![Frame::Value]);
+}
+
+/// Same as `json4::JsonArray::generate`
+fn json_array<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
+    let mut stack = Vec::new();
+    array(rng, depth, buf, &mut stack);
+    run(rng, config, depth, buf, stack);
+}
+
+/// Same as `json4::JsonObject::generate`
+fn json_object<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
+    let mut stack = Vec::new();
+    object(rng, config, depth, buf, &mut stack);
+    run(rng, config, depth, buf, stack);
+}
+
+/// Same as `json4::JsonPair::generate`
+fn json_pair<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
+    *depth += 1;
+    JsonString::generate(rng, config, depth, buf);
+    buf.push(b':');
+    run(rng, config, depth, buf, vec![Frame::Value]);
+}
+
+/// Rule of the grammar called `name`, generated with an explicit stack if it
+/// is recursive
+pub fn rule(name: &str) -> Option<GenerateFn> {
+    Some(match name {
+        "Json" => generate,
+        "JsonValue" => json_value,
+        "JsonArray" => json_array,
+        "JsonObject" => json_object,
+        "JsonPair" => json_pair,
+        _ => return json4::rule(name),
+    })
+}
+
+/// Generate the frames of `stack` until it is empty
+fn run<S: Sink + ?Sized>(
+    rng: &mut Rng,
+    config: &Config,
+    depth: &mut u64,
+    buf: &mut S,
+    mut stack: Vec<Frame>,
+) {
+    while let Some(frame) = stack.last_mut() {
+        match frame {
+            Frame::Value => {
+                stack.pop();
+                value(rng, config, depth, buf, &mut stack);
+            }
+            Frame::Array { left } => {
+                let left = left.get_or_insert_with(|| repeat(rng, config, *depth));
+                if *left == 0 {
+                    stack.pop();
+                    buf.push(b']');
+                    continue;
+                }
+                *left -= 1;
+                buf.push(b',');
+                stack.push(Frame::Value);
+            }
+            Frame::Object { left, keys, ranges } => {
+                let left = left.get_or_insert_with(|| repeat(rng, config, *depth));
+                if *left > 0 {
+                    *left -= 1;
+                    if pair(rng, config, depth, buf, b",", keys, ranges) {
+                        stack.push(Frame::Value);
+                        continue;
+                    }
+                    // No usable key left, close the object early
+                }
+                stack.pop();
+                buf.push(b'}');
+            }
+        }
+    }
+}
+
+/// Number of repetitions of `(',' value)*` and `(',' pair)*`, drawn once the
+/// first value is done
+fn repeat(rng: &mut Rng, config: &Config, depth: u64) -> usize {
+    if depth <= config.max_depth {
+        rng.next() % config.max_repeat
+    } else {
+        0
+    }
+}
+
+/// Start of a `JsonValue`, pushing what is left of it on `stack`
+fn value<S: Sink + ?Sized>(
+    rng: &mut Rng,
+    config: &Config,
+    depth: &mut u64,
+    buf: &mut S,
+    stack: &mut Vec<Frame>,
+) {
+    *depth += 1;
+    if *depth >= config.max_depth {
+        return Number::generate(rng, config, depth, buf);
+    }
+    match rng.weighted(&config.value_weights) {
+        0 => JsonString::generate(rng, config, depth, buf),
+        1 => Number::generate(rng, config, depth, buf),
+        2 => object(rng, config, depth, buf, stack),
+        3 => array(rng, depth, buf, stack),
+        4 => buf.extend_from_slice("true".as_bytes()),
+        5 => buf.extend_from_slice("false".as_bytes()),
+        6 => buf.extend_from_slice("null".as_bytes()),
+        _ => unreachable!(),
+    }
+}
+
+/// Start of a `JsonArray`, pushing what is left of it on `stack`
+fn array<S: Sink + ?Sized>(rng: &mut Rng, depth: &mut u64, buf: &mut S, stack: &mut Vec<Frame>) {
+    *depth += 1;
+    match rng.next() % 10 {
+        0 => {
+            buf.push(b'[');
+            buf.push(b']');
+        }
+        _ => {
+            buf.push(b'[');
+            stack.push(Frame::Array { left: None });
+            stack.push(Frame::Value);
+        }
+    }
+}
+
+/// Start of a `JsonObject`, pushing what is left of it on `stack`
+fn object<S: Sink + ?Sized>(
+    rng: &mut Rng,
+    config: &Config,
+    depth: &mut u64,
+    buf: &mut S,
+    stack: &mut Vec<Frame>,
+) {
+    *depth += 1;
+    match rng.next() % 10 {
+        0 => {
+            buf.push(b'{');
+            buf.push(b'}');
+        }
+        _ => {
+            buf.push(b'{');
+            let mut keys = Vec::new();
+            let mut ranges = Vec::new();
+            let started = pair(rng, config, depth, buf, b"", &mut keys, &mut ranges);
+            stack.push(Frame::Object {
+                left: None,
+                keys,
+                ranges,
+            });
+            if started {
+                stack.push(Frame::Value);
+            }
+        }
+    }
+}
+
+/// `JsonPair` after `separator` up to its value, with the key chosen
+/// according to `config.key_mode`. Returns `false`, leaving `buf` untouched,
+/// if no fitting key was found.
+fn pair<S: Sink + ?Sized>(
+    rng: &mut Rng,
+    config: &Config,
+    depth: &mut u64,
+    buf: &mut S,
+    separator: &[u8],
+    keys: &mut Vec<u8>,
+    ranges: &mut Vec<(usize, usize)>,
+) -> bool {
+    match config.key_mode {
+        KeyMode::Random => {
+            buf.extend_from_slice(separator);
+            *depth += 1;
+            JsonString::generate(rng, config, depth, buf);
+        }
+        _ => match JsonObject::key(rng, config, depth, keys, ranges) {
+            Some((start, end)) => {
+                buf.extend_from_slice(separator);
+                buf.extend_from_slice(&keys[start..end]);
+            }
+            None => return false,
+        },
+    }
+    buf.push(b':');
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_as_recursive() {
+        for name in ["Json", "JsonValue", "JsonArray", "JsonObject", "JsonPair"] {
+            let (recursive, explicit) = (json4::rule(name).unwrap(), rule(name).unwrap());
+            for key_mode in [KeyMode::Random, KeyMode::Unique, KeyMode::Duplicate(40)] {
+                for max_depth in [4, 32, 128] {
+                    let config = Config {
+                        key_mode,
+                        max_depth,
+                        ..Config::default()
+                    };
+                    for seed in 0..100 {
+                        let (mut a, mut b) = (Vec::new(), Vec::new());
+                        let (mut depth_a, mut depth_b) = (0, 0);
+                        recursive(&mut Rng::seeded(seed), &config, &mut depth_a, &mut a);
+                        explicit(&mut Rng::seeded(seed), &config, &mut depth_b, &mut b);
+                        assert!(a == b && depth_a == depth_b, "{} seed {}", name, seed);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // Only arrays, so every value but the last ones nests further
+        let config = Config {
+            max_repeat: 2,
+            max_depth: 1_000_000,
+            value_weights: [0, 0, 0, 1, 0, 0, 0],
+            ..Config::default()
+        };
+        let mut buf = Vec::new();
+        generate(&mut Rng::seeded(0), &config, &mut 0, &mut buf);
+        let (mut nesting, mut deepest) = (0, 0);
+        for &b in &buf {
+            match b {
+                b'[' => nesting += 1,
+                b']' => nesting -= 1,
+                _ => {}
+            }
+            deepest = deepest.max(nesting);
+        }
+
+        // Far deeper than the native stack takes the recursive rules
+        assert_eq!(nesting, 0);
+        assert!(deepest > 100_000, "nesting {}", deepest);
+    }
+}
@@ -20,14 +20,16 @@ fn rdrand() -> u64 {
     res
 }
 
-lazy_static! {
-    static ref SEED: AtomicU64 = AtomicU64::new(rdrand());
-    static ref COUNT: AtomicU64 = AtomicU64::new(0);
-}
+/// Xorshift state, seeded on first use
+static SEED: AtomicU64 = AtomicU64::new(0);
+static COUNT: AtomicU64 = AtomicU64::new(0);
 
 /// Rng seeded with rdrand that is generated using xorshift
 fn rand() -> usize {
     let mut val = SEED.load(Ordering::SeqCst);
+    if val == 0 {
+        val = rdrand();
+    }
     val ^= val << 13;
     val ^= val >> 17;
     val ^= val << 43;
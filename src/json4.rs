@@ -7,7 +7,10 @@
 //!
 //! Enums instead of structs so that each Object can't be instantiated
 //! and can only be called via generate.
-use crate::{Generate, Rng, MAX_DEPTH, MAX_REPEAT};
+use alloc::vec::Vec;
+
+use crate::value::same_string;
+use crate::{Config, Generate, GenerateFn, Rng, Sink};
 
 /// Exponent object
 ///
@@ -21,27 +24,27 @@ use crate::{Generate, Rng, MAX_DEPTH, MAX_REPEAT};
 #[derive(Debug)]
 pub enum Exp {}
 impl Generate for Exp {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
         // : [Ee] [+\-]? INT
         // [Ee]
         match rng.next() % 2 {
-            0 => buf.push('e' as u8),
-            1 => buf.push('E' as u8),
+            0 => buf.push(b'e'),
+            1 => buf.push(b'E'),
             _ => unreachable!(),
         }
 
         // [+\-]?
         if (rng.next() % 2) == 1 {
             match rng.next() % 2 {
-                0 => buf.push('+' as u8),
-                1 => buf.push('-' as u8),
+                0 => buf.push(b'+'),
+                1 => buf.push(b'-'),
                 _ => unreachable!(),
             }
         }
 
         // INT
-        Int::generate(rng, depth, buf);
+        Int::generate(rng, config, depth, buf);
     }
 }
 
@@ -57,17 +60,17 @@ impl Generate for Exp {
 #[derive(Debug)]
 pub enum Int {}
 impl Generate for Int {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
         // '0' | [1-9] [0-9]*
         match rng.next() % 2 {
             // '0'
-            0 => buf.push('0' as u8),
+            0 => buf.push(b'0'),
             1 => {
                 // [1-9] [0-9]*
-                buf.push(('1' as u8 + (rng.next() % 9) as u8));
-                for _ in 0..(rng.next() % MAX_REPEAT) {
-                    buf.push(('0' as u8 + (rng.next() % 10) as u8));
+                buf.push(b'1' + (rng.next() % 9) as u8);
+                for _ in 0..(rng.next() % config.max_repeat) {
+                    buf.push(b'0' + (rng.next() % 10) as u8);
                 }
             }
             _ => unreachable!(),
@@ -89,30 +92,30 @@ impl Generate for Int {
 #[derive(Debug)]
 pub enum Number {}
 impl Generate for Number {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
         // '-'? INT ('.' [0-9] +)? EXP?
 
         // '-'?
         if rng.next() % 2 == 1 {
-            buf.push('-' as u8);
+            buf.push(b'-');
         }
 
         // INT
-        Int::generate(rng, depth, buf);
+        Int::generate(rng, config, depth, buf);
 
         // ('.' [0-9]+)?
         if rng.next() % 2 == 1 {
-            buf.push('.' as u8);
-            buf.push(('0' as u8 + (rng.next() % 10) as u8));
-            for _ in 0..(rng.next() % MAX_REPEAT) {
-                buf.push(('0' as u8 + (rng.next() % 10) as u8));
+            buf.push(b'.');
+            buf.push(b'0' + (rng.next() % 10) as u8);
+            for _ in 0..(rng.next() % config.max_repeat) {
+                buf.push(b'0' + (rng.next() % 10) as u8);
             }
         }
 
         // EXP?
         if rng.next() % 2 == 1 {
-            Exp::generate(rng, depth, buf);
+            Exp::generate(rng, config, depth, buf);
         }
     }
 }
@@ -128,15 +131,14 @@ impl Generate for Number {
 #[derive(Debug)]
 pub enum Hex {}
 impl Generate for Hex {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, _config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
         // : [0-9a-fA-F]
-        static values: [u8; 22] = [
-            '0' as u8, '1' as u8, '2' as u8, '3' as u8, '4' as u8, '5' as u8, '6' as u8, '7' as u8,
-            '8' as u8, '9' as u8, 'a' as u8, 'b' as u8, 'c' as u8, 'd' as u8, 'e' as u8, 'f' as u8,
-            'A' as u8, 'B' as u8, 'C' as u8, 'D' as u8, 'E' as u8, 'F' as u8,
+        static VALUES: [u8; 22] = [
+            b'0', b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'a', b'b', b'c', b'd',
+            b'e', b'f', b'A', b'B', b'C', b'D', b'E', b'F',
         ];
-        buf.push(values[rng.next() as usize % values.len()] as u8);
+        buf.push(VALUES[rng.next() % VALUES.len()]);
     }
 }
 
@@ -151,13 +153,13 @@ impl Generate for Hex {
 #[derive(Debug)]
 pub enum Unicode {}
 impl Generate for Unicode {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
-        buf.push('u' as u8);
-        Hex::generate(rng, depth, buf);
-        Hex::generate(rng, depth, buf);
-        Hex::generate(rng, depth, buf);
-        Hex::generate(rng, depth, buf);
+        buf.push(b'u');
+        Hex::generate(rng, config, depth, buf);
+        Hex::generate(rng, config, depth, buf);
+        Hex::generate(rng, config, depth, buf);
+        Hex::generate(rng, config, depth, buf);
     }
 }
 
@@ -172,23 +174,26 @@ impl Generate for Unicode {
 #[derive(Debug)]
 pub enum SafeCodePoint {}
 impl Generate for SafeCodePoint {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, _config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
-        let mut num;
+        let value;
         loop {
             // Try all possible unicode values
-            num = (rng.next() % 0x10ffff) as u32;
+            let num = (rng.next() % 0x10ffff) as u32;
 
             // Ignore \u0000..\u001f and " and \
-            if num < 0x20 || num == ('"' as u8 as u32) || num == ('\\' as u8 as u32) {
+            if num < 0x20 || num == (b'"' as u32) || num == (b'\\' as u32) {
                 continue;
             }
 
-            break;
+            // Surrogates aren't characters and can't be encoded as UTF-8
+            if let Some(c) = core::char::from_u32(num) {
+                value = c;
+                break;
+            }
         }
 
-        buf.push((num & 0xff) as u8);
-        buf.push((num >> 16) as u8);
+        buf.extend_from_slice(value.encode_utf8(&mut [0; 4]).as_bytes());
     }
 }
 
@@ -203,22 +208,19 @@ impl Generate for SafeCodePoint {
 #[derive(Debug)]
 pub enum Escape {}
 impl Generate for Escape {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
         // '\\' (["\\/bfnrt] | UNICODE)
 
         // '\\'
-        buf.push('\\' as u8);
+        buf.push(b'\\');
         match rng.next() % 2 {
             // | UNICODE)
-            0 => Unicode::generate(rng, depth, buf),
+            0 => Unicode::generate(rng, config, depth, buf),
             1 => {
                 // (["\\/bfnrt]
-                let values = [
-                    '"' as u8, '\\' as u8, '/' as u8, 'b' as u8, 
-                    'f' as u8, 'n' as u8, 'r' as u8, 't' as u8,
-                ];
-                buf.push(values[rng.next() as usize % values.len()]);
+                let values = [b'"', b'\\', b'/', b'b', b'f', b'n', b'r', b't'];
+                buf.push(values[rng.next() % values.len()]);
             }
             _ => unreachable!(),
         }
@@ -236,19 +238,19 @@ impl Generate for Escape {
 #[derive(Debug)]
 pub enum JsonString {}
 impl Generate for JsonString {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
-        buf.push('"' as u8);
-        if *depth <= MAX_DEPTH {
-            for _ in 0..(rng.next() % MAX_REPEAT) {
+        buf.push(b'"');
+        if *depth <= config.max_depth {
+            for _ in 0..(rng.next() % config.max_repeat) {
                 match rng.next() % 2 {
-                    0 => Escape::generate(rng, depth, buf),
-                    1 => SafeCodePoint::generate(rng, depth, buf),
+                    0 => Escape::generate(rng, config, depth, buf),
+                    1 => SafeCodePoint::generate(rng, config, depth, buf),
                     _ => unreachable!(),
                 }
             }
         }
-        buf.push('"' as u8);
+        buf.push(b'"');
     }
 }
 
@@ -270,23 +272,30 @@ impl Generate for JsonString {
 #[derive(Debug)]
 pub enum JsonValue {}
 
+/// Default weights of the `JsonValue` alternatives, in grammar order
+pub const VALUE_WEIGHTS: [u32; 7] = [15, 15, 30, 38, 1, 1, 1];
+
+/// Names of the `JsonValue` alternatives, in grammar order
+pub const VALUE_NAMES: [&str; 7] = [
+    "string", "number", "object", "array", "true", "false", "null",
+];
+
 impl Generate for JsonValue {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
-        if *depth >= MAX_DEPTH {
-            return Number::generate(rng, depth, buf);
+        if *depth >= config.max_depth {
+            return Number::generate(rng, config, depth, buf);
         }
-        let value = match rng.next() % 100 {
-            0..15 => JsonString::generate(rng, depth, buf),
-            15..30 => Number::generate(rng, depth, buf),
-            30..60 => JsonObject::generate(rng, depth, buf),
-            60..98 => JsonArray::generate(rng, depth, buf),
-            98 => buf.extend_from_slice("true".as_bytes()),
-            99 => buf.extend_from_slice("false".as_bytes()),
-            100 => buf.extend_from_slice("null".as_bytes()),
+        match rng.weighted(&config.value_weights) {
+            0 => JsonString::generate(rng, config, depth, buf),
+            1 => Number::generate(rng, config, depth, buf),
+            2 => JsonObject::generate(rng, config, depth, buf),
+            3 => JsonArray::generate(rng, config, depth, buf),
+            4 => buf.extend_from_slice("true".as_bytes()),
+            5 => buf.extend_from_slice("false".as_bytes()),
+            6 => buf.extend_from_slice("null".as_bytes()),
             _ => unreachable!(),
-        };
-        value
+        }
     }
 }
 
@@ -302,11 +311,11 @@ impl Generate for JsonValue {
 #[derive(Debug)]
 pub enum JsonPair {}
 impl Generate for JsonPair {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
-        JsonString::generate(rng, depth, buf);
-        buf.push(':' as u8);
-        JsonValue::generate(rng, depth, buf);
+        JsonString::generate(rng, config, depth, buf);
+        buf.push(b':');
+        JsonValue::generate(rng, config, depth, buf);
     }
 }
 
@@ -322,28 +331,45 @@ impl Generate for JsonPair {
 #[derive(Debug)]
 pub enum JsonArray {}
 impl Generate for JsonArray {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
         match rng.next() % 10 {
             0 => {
-                buf.push('[' as u8);
-                buf.push(']' as u8);
+                buf.push(b'[');
+                buf.push(b']');
             }
             _ => {
-                buf.push('[' as u8);
-                JsonValue::generate(rng, depth, buf);
-                if *depth <= MAX_DEPTH {
-                    for _ in 0..(rng.next() % MAX_REPEAT) {
-                        buf.push(',' as u8);
-                        JsonValue::generate(rng, depth, buf);
+                buf.push(b'[');
+                JsonValue::generate(rng, config, depth, buf);
+                if *depth <= config.max_depth {
+                    for _ in 0..(rng.next() % config.max_repeat) {
+                        buf.push(b',');
+                        JsonValue::generate(rng, config, depth, buf);
                     }
                 }
-                buf.push(']' as u8);
+                buf.push(b']');
             }
         }
     }
 }
 
+/// Key policy for the pairs of a single `JsonObject`
+///
+/// RFC 8259 only says names within an object SHOULD be unique and leaves
+/// duplicates up to the parser (last-wins, first-wins or reject).
+#[derive(Debug, Clone, Copy)]
+pub enum KeyMode {
+    /// Keys are generated independently, duplicates only happen by chance
+    Random,
+
+    /// Every key within an object is distinct
+    Unique,
+
+    /// Each pair after the first reuses an earlier key of the same object
+    /// with the given percent chance
+    Duplicate(usize),
+}
+
 /// JsonObject generation
 ///
 /// ANTLR4:
@@ -356,28 +382,138 @@ impl Generate for JsonArray {
 #[derive(Debug)]
 pub enum JsonObject {}
 impl Generate for JsonObject {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
         match rng.next() % 10 {
             0 => {
-                buf.push('{' as u8);
-                buf.push('}' as u8);
+                buf.push(b'{');
+                buf.push(b'}');
             }
             _ => {
-                buf.push('{' as u8);
-                JsonPair::generate(rng, depth, buf);
-                if *depth <= MAX_DEPTH {
-                    for _ in 0..(rng.next() % MAX_REPEAT) {
-                        buf.push(',' as u8);
-                        JsonPair::generate(rng, depth, buf);
+                buf.push(b'{');
+                match config.key_mode {
+                    KeyMode::Random => {
+                        JsonPair::generate(rng, config, depth, buf);
+                        if *depth <= config.max_depth {
+                            for _ in 0..(rng.next() % config.max_repeat) {
+                                buf.push(b',');
+                                JsonPair::generate(rng, config, depth, buf);
+                            }
+                        }
+                    }
+                    _ => {
+                        // Keys emitted so far in this object and their byte
+                        // ranges, kept aside as sinks can't be read back
+                        let mut keys = Vec::new();
+                        let mut ranges = Vec::new();
+                        JsonObject::keyed_pair(
+                            rng,
+                            config,
+                            depth,
+                            buf,
+                            b"",
+                            &mut keys,
+                            &mut ranges,
+                        );
+                        if *depth <= config.max_depth {
+                            for _ in 0..(rng.next() % config.max_repeat) {
+                                if !JsonObject::keyed_pair(
+                                    rng,
+                                    config,
+                                    depth,
+                                    buf,
+                                    b",",
+                                    &mut keys,
+                                    &mut ranges,
+                                ) {
+                                    // No usable key left, close the object early
+                                    break;
+                                }
+                            }
+                        }
                     }
                 }
-                buf.push('}' as u8);
+                buf.push(b'}');
             }
         }
     }
 }
 
+impl JsonObject {
+    /// Number of keys tried in `KeyMode::Unique` before giving up on a pair
+    pub(crate) const KEY_ATTEMPTS: usize = 8;
+
+    /// Same as `JsonPair` after `separator`, but with the key chosen
+    /// according to `config.key_mode`
+    ///
+    /// `keys` holds the keys already emitted in this object, at the byte
+    /// ranges in `ranges`. Returns `false`, leaving `buf` untouched, if no
+    /// fitting key was found.
+    fn keyed_pair<S: Sink + ?Sized>(
+        rng: &mut Rng,
+        config: &Config,
+        depth: &mut u64,
+        buf: &mut S,
+        separator: &[u8],
+        keys: &mut Vec<u8>,
+        ranges: &mut Vec<(usize, usize)>,
+    ) -> bool {
+        let (start, end) = match JsonObject::key(rng, config, depth, keys, ranges) {
+            Some(key) => key,
+            None => return false,
+        };
+        buf.extend_from_slice(separator);
+        buf.extend_from_slice(&keys[start..end]);
+        buf.push(b':');
+        JsonValue::generate(rng, config, depth, buf);
+        true
+    }
+
+    /// Key of the next pair, chosen according to `config.key_mode` among
+    /// `keys` as in `keyed_pair`, and recorded in `ranges`. Returns its range
+    /// in `keys`, or `None` if no fitting key was found.
+    pub(crate) fn key(
+        rng: &mut Rng,
+        config: &Config,
+        depth: &mut u64,
+        keys: &mut Vec<u8>,
+        ranges: &mut Vec<(usize, usize)>,
+    ) -> Option<(usize, usize)> {
+        *depth += 1;
+        let start = keys.len();
+        let key = match config.key_mode {
+            KeyMode::Duplicate(percent) if !ranges.is_empty() && rng.next() % 100 < percent => {
+                ranges[rng.next() % ranges.len()]
+            }
+            KeyMode::Unique => {
+                let mut attempts = 0;
+                loop {
+                    JsonString::generate(rng, config, depth, keys);
+
+                    // Compared decoded, `"\u0041"` and `"A"` are the same key
+                    let new = &keys[start..];
+                    if !ranges.iter().any(|&(s, e)| same_string(&keys[s..e], new)) {
+                        break (start, keys.len());
+                    }
+
+                    // Past `max_depth` every key is "", so the retries have to be bounded
+                    keys.truncate(start);
+                    attempts += 1;
+                    if attempts == JsonObject::KEY_ATTEMPTS {
+                        return None;
+                    }
+                }
+            }
+            _ => {
+                JsonString::generate(rng, config, depth, keys);
+                (start, keys.len())
+            }
+        };
+        ranges.push(key);
+        Some(key)
+    }
+}
+
 /// Top level JSON object
 ///
 /// ANTLR4:
@@ -390,8 +526,95 @@ impl Generate for JsonObject {
 #[derive(Debug)]
 pub enum Json {}
 impl Generate for Json {
-    fn generate(rng: &mut Rng, depth: &mut u64, buf: &mut Vec<u8>) {
+    fn generate<S: Sink + ?Sized>(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut S) {
         *depth += 1;
-        JsonValue::generate(rng, depth, buf)
+        JsonValue::generate(rng, config, depth, buf)
+    }
+}
+
+/// Rule of the grammar called `name`
+pub fn rule(name: &str) -> Option<GenerateFn> {
+    Some(match name {
+        "Json" => Json::generate,
+        "JsonObject" => JsonObject::generate,
+        "JsonPair" => JsonPair::generate,
+        "JsonArray" => JsonArray::generate,
+        "JsonValue" => JsonValue::generate,
+        "JsonString" => JsonString::generate,
+        "Escape" => Escape::generate,
+        "Unicode" => Unicode::generate,
+        "Hex" => Hex::generate,
+        "SafeCodePoint" => SafeCodePoint::generate,
+        "Number" => Number::generate,
+        "Int" => Int::generate,
+        "Exp" => Exp::generate,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    /// Whether some object in `value` has the same key twice
+    fn repeats_key(value: &Value) -> bool {
+        match value {
+            Value::Array(values) => values.iter().any(repeats_key),
+            Value::Object(pairs) => pairs.iter().enumerate().any(|(i, (key, value))| {
+                pairs[..i].iter().any(|(other, _)| other == key) || repeats_key(value)
+            }),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn unique_keys_never_repeat() {
+        let config = Config {
+            key_mode: KeyMode::Unique,
+            ..Config::default()
+        };
+        let mut objects = 0;
+        for seed in 0..500 {
+            let mut buf = Vec::new();
+            JsonObject::generate(&mut Rng::seeded(seed), &config, &mut 0, &mut buf);
+            let value = Value::parse(&buf).unwrap();
+            assert!(!repeats_key(&value), "seed {}", seed);
+            objects += matches!(value, Value::Object(pairs) if pairs.len() > 1) as usize;
+        }
+        assert!(objects > 100);
+    }
+
+    #[test]
+    fn duplicate_keys_at_rate() {
+        for percent in [0, 10, 50, 90, 100] {
+            // Flat objects with long keys, so that fresh keys hardly ever
+            // match by chance, and a budget that never cuts them short
+            let config = Config {
+                max_repeat: 64,
+                max_depth: u64::MAX,
+                key_mode: KeyMode::Duplicate(percent),
+                value_weights: [0, 0, 0, 0, 1, 1, 1],
+            };
+            let (mut repeated, mut pairs) = (0, 0);
+            for seed in 0..1000 {
+                let mut buf = Vec::new();
+                JsonObject::generate(&mut Rng::seeded(seed), &config, &mut 0, &mut buf);
+                if let Value::Object(object) = Value::parse(&buf).unwrap() {
+                    for (i, (key, _)) in object.iter().enumerate().skip(1) {
+                        repeated += object[..i].iter().any(|(other, _)| other == key) as usize;
+                        pairs += 1;
+                    }
+                }
+            }
+            let rate = repeated as f64 * 100. / pairs as f64;
+            assert!(pairs > 10_000, "{} pairs", pairs);
+            assert!(
+                (rate - percent as f64).abs() < 2.,
+                "{}% instead of {}%",
+                rate,
+                percent
+            );
+        }
     }
 }
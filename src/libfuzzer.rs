@@ -0,0 +1,81 @@
+//! libFuzzer custom mutator
+//!
+//! With the `cdylib` feature, which the `capi` package building `libbnf.so`
+//! turns on, the shared library exports the hooks libFuzzer
+//! looks for, so linking it into a fuzz target makes libFuzzer mutate inputs
+//! with the JSON grammar instead of flipping bytes:
+//!
+//! ```text
+//! cargo build --release
+//! clang -fsanitize=fuzzer target.c target/release/libbnf.so -o target
+//! ```
+//!
+//! Both hooks parse their inputs and mutate them structurally, see `mutate`.
+use std::cell::RefCell;
+
+use crate::mutate::{crossover, mutate};
+use crate::{Config, Rng};
+
+thread_local! {
+    /// Output buffer reused across calls
+    static OUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Slice of `size` bytes at `data`, which may be null if `size` is 0
+pub(crate) unsafe fn input<'a>(data: *const u8, size: usize) -> &'a [u8] {
+    match size {
+        0 => &[],
+        _ => core::slice::from_raw_parts(data, size),
+    }
+}
+
+/// Mutate the `size` bytes at `data` in place into at most `max_size` bytes,
+/// returning the new size
+///
+/// # Safety
+///
+/// `data` must be valid for reads of `size` and writes of `max_size` bytes.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn LLVMFuzzerCustomMutator(
+    data: *mut u8,
+    size: usize,
+    max_size: usize,
+    seed: u32,
+) -> usize {
+    OUT.with(|out| {
+        let out = &mut *out.borrow_mut();
+        let rng = &mut Rng::seeded(seed as u64);
+        mutate(input(data, size), rng, &Config::default(), max_size, out);
+        core::ptr::copy_nonoverlapping(out.as_ptr(), data, out.len());
+        out.len()
+    })
+}
+
+/// Combine `data1` and `data2` into at most `max_out_size` bytes at `out`,
+/// returning their number
+///
+/// # Safety
+///
+/// `data1` and `data2` must be valid for reads of `size1` and `size2` bytes
+/// and `out` for writes of `max_out_size` bytes.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn LLVMFuzzerCustomCrossOver(
+    data1: *const u8,
+    size1: usize,
+    data2: *const u8,
+    size2: usize,
+    out: *mut u8,
+    max_out_size: usize,
+    seed: u32,
+) -> usize {
+    OUT.with(|buf| {
+        let buf = &mut *buf.borrow_mut();
+        let rng = &mut Rng::seeded(seed as u64);
+        let (a, b) = (input(data1, size1), input(data2, size2));
+        crossover(a, b, rng, &Config::default(), max_out_size, buf);
+        core::ptr::copy_nonoverlapping(buf.as_ptr(), out, buf.len());
+        buf.len()
+    })
+}
@@ -1,81 +1,276 @@
-#![feature(duration_float)]
-#![feature(asm)]
-#![feature(exclusive_range_pattern)]
-use std::collections::HashSet;
-
-extern crate lazy_static;
-
-use std::io::Write;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::channel;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+
+use bnf::corpus::{Corpus, Dedup, Meta};
+use bnf::diff::Differential;
+use bnf::exec::{Delivery, Target};
+use bnf::float::GenerateFloat;
+use bnf::harness::{self, Harness};
+use bnf::interrupt::{catch_interrupt, interrupted};
+use bnf::json4::*;
+use bnf::nearmiss::GenerateNearMiss;
+use bnf::oracle::GenerateValue;
+use bnf::schema::Schema;
+use bnf::value::Value;
+use bnf::{clock_seed, input_seed, Config, GenerateFn, Rng};
+
+mod cli;
+use cli::{Args, Mode};
+
+mod batch;
+use batch::{Pool, BATCH_INPUTS};
+
+mod ring;
+use ring::{RingReader, RingWriter};
+
+mod server;
+
+/// `GenerateValue::generate_value` of a rule
+type GenerateValueFn = fn(&mut Rng, &Config, &mut u64, &mut Vec<u8>) -> Value;
 
-// mod json;
-// use json::*;
-//
-// mod json2;
-// use json2::*;
+/// What every worker generates, as picked on the command line
+#[derive(Clone)]
+enum Task {
+    Rule(GenerateFn),
+    Oracle(GenerateValueFn),
+    Float,
+    NearMiss,
+    Schema(Arc<Schema>, Mode),
+}
 
-// pub mod json3;
-// use json3::*;
-//
-pub mod json4;
-use json4::*;
+impl Task {
+    fn new(args: &Args) -> Result<Task, String> {
+        if let Some(path) = &args.schema {
+            if args.iterative {
+                return Err("--iterative doesn't support schemas".to_string());
+            }
+            let doc = std::fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+            let schema = Schema::parse(&doc).map_err(|err| format!("{}: {}", path, err))?;
+            return match args.mode {
+                Mode::Valid | Mode::Invalid => Ok(Task::Schema(Arc::new(schema), args.mode)),
+                _ => Err("schemas only support the valid and invalid modes".to_string()),
+            };
+        }
+        if args.grammar != "json" {
+            return Err(format!("unknown grammar {}", args.grammar));
+        }
 
-mod rng;
-use rng::Rng;
+        let rule = args.rule.as_str();
+        let unsupported = || format!("rule {} doesn't support this mode", rule);
+        match args.mode {
+            Mode::Valid => {
+                let lookup = if args.iterative {
+                    bnf::iterative::rule
+                } else {
+                    bnf::json4::rule
+                };
+                let rule = lookup(rule).ok_or_else(|| format!("unknown rule {}", rule))?;
+                Ok(Task::Rule(rule))
+            }
+            _ if args.iterative => Err("--iterative only supports the valid mode".to_string()),
+            Mode::Oracle => {
+                let rule = match rule {
+                    "Json" => Json::generate_value,
+                    "JsonObject" => JsonObject::generate_value,
+                    "JsonArray" => JsonArray::generate_value,
+                    "JsonValue" => JsonValue::generate_value,
+                    "JsonString" => JsonString::generate_value,
+                    "Number" => Number::generate_value,
+                    _ => return Err(unsupported()),
+                };
+                Ok(Task::Oracle(rule))
+            }
+            Mode::Float if rule == "Number" || rule == "Json" => Ok(Task::Float),
+            Mode::Invalid if rule == "Json" => Ok(Task::NearMiss),
+            _ => Err(unsupported()),
+        }
+    }
 
-pub const MAX_REPEAT: usize = 16;
-pub const MAX_DEPTH: u64 = 128;
+    /// Append one input, with its expected result for the oracle modes,
+    /// generated from `seed` to `buf`, returning the depth reached. Returns
+    /// `None` if nothing fitting could be generated.
+    fn run(&self, seed: u64, config: &Config, buf: &mut Vec<u8>) -> Option<u64> {
+        let rng = &mut Rng::seeded(seed);
+        let mut depth = 0;
+        let found = self.run_into(rng, config, &mut depth, buf);
+        found.then_some(depth)
+    }
 
-/*
-pub trait Generate {
-    fn generate(rng: &mut Rng, recursion: &mut u64) -> String;
+    fn run_into(&self, rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut Vec<u8>) -> bool {
+        match self {
+            Task::Rule(rule) => rule(rng, config, depth, buf),
+            Task::Oracle(rule) => {
+                let value = rule(rng, config, depth, buf);
+                buf.push(b'\t');
+                value.write(buf);
+            }
+            Task::Float => {
+                let float = Number::generate_float(rng, config, depth, buf);
+                let bits = format!("\t{:016x}\t{:08x}", float.f64_bits, float.f32_bits);
+                buf.extend_from_slice(bits.as_bytes());
+            }
+            Task::NearMiss => return Json::generate_near_miss(rng, config, depth, buf).is_some(),
+            Task::Schema(schema, Mode::Invalid) => {
+                return schema.generate_invalid(rng, config, depth, buf)
+            }
+            Task::Schema(schema, _) => return schema.generate(rng, config, depth, buf),
+        }
+        true
+    }
 }
-*/
 
-pub trait Generate {
-    fn generate(rng: &mut Rng, recursion: &mut u64, buf: &mut Vec<u8>);
+fn fail(msg: &str) -> ! {
+    eprintln!("error: {}\nRun with --help for usage", msg);
+    process::exit(2);
 }
 
 fn main() {
-    // Channel used to send completed work out of the threads to the collector
-    let (tx, rx) = channel();
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|msg| fail(&msg));
+    if args.help {
+        print!("{}", cli::USAGE);
+        return;
+    }
+    if let Some(path) = &args.read_shm {
+        return read_ring(&args, path);
+    }
+    let task = Task::new(&args).unwrap_or_else(|msg| fail(&msg));
+    let config = args.config;
+
+    if let Some(seed) = args.replay {
+        let mut input = Vec::new();
+        if task.run(seed, &config, &mut input).is_none() {
+            fail(&format!("seed {} doesn't generate an input", seed));
+        }
+        let res = match args.output.as_deref() {
+            None | Some("-") => args.format.write(&mut io::stdout(), &input),
+            Some(path) => {
+                File::create(path).and_then(|mut file| args.format.write(&mut file, &input))
+            }
+        };
+        if let Err(err) = res {
+            eprintln!("error: writing output: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(path) = &args.serve {
+        if let Err(err) = server::serve(Path::new(path), &args) {
+            eprintln!("error: {}: {}", path, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.exec.len() + args.fuzz_parser as usize > 1 {
+        return fuzz_diff(&args, &task);
+    }
+    if args.fuzz_parser {
+        return fuzz_parser(&args, &task);
+    }
+    if let Some(program) = args.exec.first() {
+        return fuzz_program(&args, &task, program);
+    }
+
+    // Pick a seed that can be reported, so any run can be reproduced
+    let seed = args.seed.unwrap_or_else(clock_seed);
+
+    let mut output = open_output(&args);
+    let mut ring = args.shm.as_ref().map(|path| {
+        RingWriter::create(Path::new(path), ring::CAPACITY)
+            .unwrap_or_else(|err| fail(&format!("{}: {}", path, err)))
+    });
+    let mut corpus = args.corpus.as_ref().map(|dir| {
+        Corpus::open(Path::new(dir), args.meta)
+            .unwrap_or_else(|err| fail(&format!("{}: {}", dir, err)))
+    });
+    let mut dedup = Dedup::default();
+    let (grammar, rule) = match &args.schema {
+        Some(path) => ("schema", path.as_str()),
+        None => (args.grammar.as_str(), args.rule.as_str()),
+    };
+
+    // Channel used to send completed batches out of the threads to the
+    // collector. Bounded, so workers wait when the collector falls behind.
+    let (tx, rx) = sync_channel(2 * args.threads);
+
+    // Batches the collector is done with, for the workers to reuse
+    let pool = Pool::new(4 * args.threads);
 
     // Kill signal for the threads to know when to stop
     let die = Arc::new(AtomicBool::new(false));
 
-    // Number threads to start
-    let num_cores = 1;
+    catch_interrupt();
+    let start = Instant::now();
 
-    // Number of results to generate
-    let max_results = 10_000_000;
+    // Next sequence number to be claimed by a worker, and the one the
+    // collector is waiting for
+    let claimed = Arc::new(AtomicU64::new(0));
+    let delivered = Arc::new(AtomicU64::new(0));
 
-    let mut aux = 0;
-    let start = unsafe { core::arch::x86_64::_rdtsc() };
-    // let start = std::time::Instant::now();
+    // How far workers may run ahead of the collector, which has to hold on to
+    // everything past the oldest missing batch
+    let window = 4 * args.threads as u64 * BATCH_INPUTS;
 
-    // Start a thread on each core generating testcases per core
-    for _ in 0..num_cores {
+    // Start the worker threads. They claim blocks of sequence numbers and
+    // derive the seed of every input from its sequence number, so the output
+    // doesn't depend on which thread generated what.
+    let mut workers = Vec::new();
+    for _ in 0..args.threads {
         let tx = tx.clone();
         let die = die.clone();
-        thread::spawn(move || {
-            let mut rng = Rng::new();
-            loop {
-                let mut buf = Vec::with_capacity(MAX_DEPTH as usize * 1024);
-                let mut depth = 0;
-                // Add the test case to the channel to be read
-                Json::generate(&mut rng, &mut depth, &mut buf);
-
-                let _ = tx.send(buf);
+        let task = task.clone();
+        let pool = pool.clone();
+        let claimed = claimed.clone();
+        let delivered = delivered.clone();
+        workers.push(thread::spawn(move || 'run: loop {
+            let block = claimed.fetch_add(BATCH_INPUTS, Ordering::Relaxed);
+            while block >= delivered.load(Ordering::Acquire) + window {
+                if die.load(Ordering::Acquire) {
+                    break 'run;
+                }
+                thread::sleep(Duration::from_micros(100));
+            }
 
+            let mut batch = pool.get(block);
+            for sequence in block..block + BATCH_INPUTS {
                 // Check if we should stop the thread
                 if die.load(Ordering::Acquire) {
                     break;
                 }
+
+                let input_seed = input_seed(seed, sequence);
+                match task.run(input_seed, &config, batch.arena()) {
+                    Some(depth) => batch.commit(input_seed, depth),
+                    None => batch.discard(),
+                }
+
+                // Add the test cases to the channel to be read, the collector
+                // hanging up means we're done
+                if batch.is_full() {
+                    if tx.send(batch).is_err() {
+                        break 'run;
+                    }
+                    batch = pool.get(sequence + 1);
+                }
             }
-        });
+
+            // Hand over the rest of the block, or what was generated before
+            // the stop. Batches without inputs still move the collector on.
+            if !batch.is_empty() && tx.send(batch).is_err() {
+                break;
+            }
+            if die.load(Ordering::Acquire) {
+                break;
+            }
+        }));
     }
 
     // No need for the tx channel side anymore since we have no more threads
@@ -83,22 +278,337 @@ fn main() {
 
     let mut generated_bytes = 0;
     let mut counter = 0;
-    for generated_input in rx.iter() {
-        generated_bytes += generated_input.len();
+    let mut last_report = start;
+
+    // Batches that arrived ahead of the next one in sequence, by first
+    // sequence number
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+
+    // Why the run is stopping, once it is. The loop then keeps draining the
+    // channel until every worker has finished its last input.
+    let mut stopping: Option<&str> = None;
+    let full = |counter: u64, generated_bytes: u64| {
+        args.count.is_some_and(|count| counter >= count)
+            || args.bytes.is_some_and(|bytes| generated_bytes >= bytes)
+    };
+    loop {
+        let elapsed = start.elapsed();
+        if args.stats && elapsed - (last_report - start) >= Duration::from_secs(1) {
+            last_report = Instant::now();
+            report(elapsed, counter, generated_bytes);
+        }
+
+        if stopping.is_none() {
+            if interrupted() {
+                stopping = Some("interrupted");
+            } else if args.time.is_some_and(|time| elapsed >= time) {
+                stopping = Some("time limit reached");
+            } else if full(counter, generated_bytes) {
+                // Checked here too as nothing is written once the limits are
+                // reached, e.g. with `-n 0` or only duplicates left
+                stopping = Some("limit reached");
+            }
+            if stopping.is_some() {
+                die.store(true, Ordering::Release);
+            }
+        }
+
+        // Wake up regularly to notice the limits and interrupts even when
+        // inputs are slow to come
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(batch) => pending.insert(batch.start, batch),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        // Inputs after a gap left by a stopped worker are never written
+        while let Some(batch) = pending.remove(&next) {
+            next = batch.end;
+            delivered.store(next, Ordering::Release);
+            for (data, input) in batch.iter() {
+                // Inputs past the count and byte limits are dropped, the ones
+                // generated before an interrupt are still written
+                if full(counter, generated_bytes) || (args.dedup && !dedup.insert(data)) {
+                    continue;
+                }
+                generated_bytes += data.len() as u64;
+                counter += 1;
+
+                if let Some(out) = &mut output {
+                    match args.format.write(out, data) {
+                        Ok(()) => {}
+
+                        // The reading end of the pipe is gone, e.g. `bnf -o - | head`
+                        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                            output = None;
+                            stopping = stopping.or(Some("output closed"));
+                            die.store(true, Ordering::Release);
+                        }
+                        Err(err) => {
+                            eprintln!("error: writing output: {}", err);
+                            process::exit(1);
+                        }
+                    }
+                }
+
+                if let Some(ring) = &mut ring {
+                    if data.len() > ring.max_input() {
+                        eprintln!("error: {} byte input doesn't fit the ring", data.len());
+                        process::exit(1);
+                    }
+
+                    // Wait for the consumer to make room, unless asked to stop
+                    while !ring.try_push(data) && !interrupted() {
+                        thread::sleep(Duration::from_micros(50));
+                    }
+                }
+
+                if let Some(corpus) = &mut corpus {
+                    let meta = Meta {
+                        seed: input.seed,
+                        grammar,
+                        rule,
+                        mode: args.mode.name(),
+                        depth: input.depth,
+                        max_depth: config.max_depth,
+                        max_repeat: config.max_repeat,
+                    };
+                    if let Err(err) = corpus.save(data, &meta) {
+                        eprintln!("error: writing corpus: {}", err);
+                        process::exit(1);
+                    }
+                }
+
+                if stopping.is_none() && full(counter, generated_bytes) {
+                    stopping = Some("limit reached");
+                    die.store(true, Ordering::Release);
+                }
+            }
+            pool.put(batch);
+        }
+    }
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    // Tell the consumer there is nothing more to come
+    drop(ring);
+
+    if let Some(mut out) = output {
+        match out.flush() {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(err) => {
+                eprintln!("error: writing output: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let reason = stopping.unwrap_or("done");
+    summary(
+        reason,
+        start,
+        counter,
+        generated_bytes,
+        &format!(" (seed {})", seed),
+    );
+}
+
+/// Final line of a run on stderr
+fn summary(reason: &str, start: Instant, inputs: u64, bytes: u64, suffix: &str) {
+    let elapsed = start.elapsed().as_secs_f64();
+    eprintln!(
+        "{}: {} inputs, {:.2} MB in {:.2} s = {:.2} MB/s, {:.0} inputs/s{}",
+        reason,
+        inputs,
+        bytes as f64 / 1000. / 1000.,
+        elapsed,
+        bytes as f64 / elapsed / 1000. / 1000.,
+        inputs as f64 / elapsed,
+        suffix
+    );
+}
+
+/// Harness set up from the command line, generating the inputs of `task`
+fn harness(args: &Args, task: &Task) -> Harness {
+    let rule = match task {
+        Task::Rule(rule) => *rule,
+        _ => fail("fuzzing needs a grammar rule in the valid mode"),
+    };
+    Harness {
+        rule,
+        rule_name: args.rule.clone(),
+        config: args.config,
+        seed: args.seed,
+        count: args.count,
+        time: args.time,
+        crashes: args.crashes.clone().into(),
+        hangs: args.hangs.clone().into(),
+        mismatches: args.mismatches.clone().into(),
+        stats: args.stats,
+    }
+}
+
+/// `--fuzz-parser`: check that `Value::parse` accepts every input
+fn fuzz_parser(args: &Args, task: &Task) {
+    let harness = harness(args, task);
+    catch_interrupt();
+    let report = harness.fuzz(|input| {
+        if let Err(err) = Value::parse(input) {
+            panic!("valid input rejected: {}", err);
+        }
+    });
+    fuzz_summary(report);
+}
+
+/// Run `program`, given after `--`, on every input
+fn fuzz_program(args: &Args, task: &Task, program: &[String]) {
+    let harness = harness(args, task);
+    let mut target = target(args, program);
+    catch_interrupt();
+    fuzz_summary(harness.exec(&mut target));
+}
+
+/// Compare the programs and the built-in parser if asked for
+fn fuzz_diff(args: &Args, task: &Task) {
+    let harness = harness(args, task);
+    let mut differential = Differential::new();
+    if args.fuzz_parser {
+        differential.add_fn("bnf", |input| {
+            let value = Value::parse(input).ok()?;
+            let mut output = Vec::new();
+            value.write(&mut output);
+            Some(output)
+        });
+    }
+    for program in &args.exec {
+        differential.add_program(&program.join(" "), target(args, program));
+    }
+    catch_interrupt();
+    fuzz_summary(harness.diff(&mut differential));
+}
+
+fn target(args: &Args, program: &[String]) -> Target {
+    let mut target = Target::new(program.to_vec());
+    target.timeout = args.timeout;
+    target.crash_codes = args.crash_codes.clone();
+    if let Some(var) = &args.env {
+        target.delivery = Delivery::Env(var.clone());
+    }
+    target
+}
+
+fn fuzz_summary(report: io::Result<harness::Report>) {
+    let report = report.unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    });
+    eprintln!(
+        "{}: {} execs, {} crashes ({} unique), {} hangs, {} mismatches in {:.2} s = {:.0} \
+         execs/s (seed {})",
+        report.reason,
+        report.execs,
+        report.crashes,
+        report.unique_crashes,
+        report.hangs,
+        report.mismatches,
+        report.elapsed.as_secs_f64(),
+        report.execs_per_sec(),
+        report.seed
+    );
+    if report.crashes > 0 || report.hangs > 0 || report.mismatches > 0 {
+        process::exit(1);
+    }
+}
+
+fn open_output(args: &Args) -> Option<Box<dyn Write>> {
+    match args.output.as_deref() {
+        None => None,
+        Some("-") => Some(Box::new(BufWriter::new(io::stdout()))),
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(Box::new(BufWriter::new(file))),
+            Err(err) => fail(&format!("{}: {}", path, err)),
+        },
+    }
+}
+
+/// Reference consumer for `--shm`, writing the inputs of the ring at `path`
+/// to the output
+fn read_ring(args: &Args, path: &str) {
+    catch_interrupt();
+    let mut output = open_output(args);
+
+    // The producer may not have set up the ring yet
+    let mut ring = loop {
+        match RingReader::open(Path::new(path)) {
+            Ok(ring) => break ring,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::WouldBlock
+                ) && !interrupted() =>
+            {
+                thread::sleep(Duration::from_millis(10))
+            }
+            Err(err) => fail(&format!("{}: {}", path, err)),
+        }
+    };
+
+    let start = Instant::now();
+    let mut counter = 0;
+    let mut bytes = 0;
+    let mut reason = "done";
+    loop {
+        let input = match ring.try_next() {
+            Ok(Some(input)) => input,
+            Ok(None) => break,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if interrupted() {
+                    reason = "interrupted";
+                    break;
+                }
+                thread::sleep(Duration::from_micros(50));
+                continue;
+            }
+            Err(err) => fail(&format!("{}: {}", path, err)),
+        };
         counter += 1;
+        bytes += input.len() as u64;
 
-        // let elapsed = start.elapsed();
-        let elapsed = unsafe { core::arch::x86_64::_rdtsc() } - start;
-        if counter & 0xfffff == 0 {
-            print!(
-                // "Time: {:10.4?} / {:10} = {:10.4} MiB/s\n",
-                "Time: {:10.2?} Mcycle / {:10.2} MB = {:10.4} cycle/byte\n",
-                // elapsed.as_secs_f64(),
-                elapsed as f64 / 1000. / 1000.,
-                generated_bytes as f64 / 1000. / 1000.,
-                // generated_bytes as f64 / elapsed as f64 / 1000. / 1000.
-                elapsed as f64 / generated_bytes as f64,
-            );
+        if let Some(out) = &mut output {
+            match args.format.write(out, input) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                    output = None;
+                    reason = "output closed";
+                    break;
+                }
+                Err(err) => {
+                    eprintln!("error: writing output: {}", err);
+                    process::exit(1);
+                }
+            }
         }
     }
+
+    if let Some(mut out) = output {
+        if let Err(err) = out.flush() {
+            eprintln!("error: writing output: {}", err);
+            process::exit(1);
+        }
+    }
+    summary(reason, start, counter, bytes, "");
+}
+
+fn report(elapsed: Duration, inputs: u64, bytes: u64) {
+    eprintln!(
+        "Time: {:10.2?} s / {:10} inputs / {:10.2} MB = {:10.4} MB/s",
+        elapsed.as_secs_f64(),
+        inputs,
+        bytes as f64 / 1000. / 1000.,
+        bytes as f64 / elapsed.as_secs_f64() / 1000. / 1000.
+    );
 }
@@ -0,0 +1,190 @@
+//! Structural mutation of JSON inputs
+//!
+//! Used by the custom mutators of coverage-guided fuzzers. An input that
+//! parses is mutated as a document: a value is replaced by a fresh one from
+//! the grammar, or an element or member is removed, duplicated or inserted,
+//! so the result stays valid JSON. Inputs that don't parse, and results that
+//! don't fit, are replaced by a fresh document from `Json::generate`.
+use crate::json4::{Json, JsonString, JsonValue};
+use crate::oracle::GenerateValue;
+use crate::value::Value;
+use crate::{Config, Generate, Rng};
+
+/// Attempts at a mutation or a document fitting `max_size`
+const ATTEMPTS: usize = 8;
+
+/// Depth budget of the values inserted by mutations, small to keep them in
+/// proportion to the input
+const FRESH_DEPTH: u64 = 16;
+
+/// Write a mutation of `input` of at most `max_size` bytes to `out`
+pub fn mutate(input: &[u8], rng: &mut Rng, config: &Config, max_size: usize, out: &mut Vec<u8>) {
+    if let Ok(doc) = Value::parse(input) {
+        for _ in 0..ATTEMPTS {
+            let mut doc = doc.clone();
+            let index = rng.next() % count(&doc);
+            mutate_node(nth_mut(&mut doc, index), rng, config);
+            out.clear();
+            doc.write(out);
+            if out.len() <= max_size {
+                return;
+            }
+        }
+    }
+    generate(rng, config, max_size, out);
+}
+
+/// Write `a` with a random value replaced by a random value of `b`, of at
+/// most `max_size` bytes, to `out`
+pub fn crossover(
+    a: &[u8],
+    b: &[u8],
+    rng: &mut Rng,
+    config: &Config,
+    max_size: usize,
+    out: &mut Vec<u8>,
+) {
+    out.clear();
+    if let (Ok(mut doc), Ok(donor)) = (Value::parse(a), Value::parse(b)) {
+        let index = rng.next() % count(&doc);
+        let donated = rng.next() % count(&donor);
+        *nth_mut(&mut doc, index) = nth(&donor, donated).clone();
+        doc.write(out);
+        if out.len() <= max_size {
+            return;
+        }
+    }
+    generate(rng, config, max_size, out);
+}
+
+/// Write a fresh document of at most `max_size` bytes to `out`, shallower
+/// with every attempt that is too large and cut short after the last one
+pub fn generate(rng: &mut Rng, config: &Config, max_size: usize, out: &mut Vec<u8>) {
+    let mut config = *config;
+    for _ in 0..ATTEMPTS {
+        out.clear();
+        Json::generate(rng, &config, &mut 0, out);
+        if out.len() <= max_size {
+            return;
+        }
+        config.max_depth /= 2;
+    }
+    out.truncate(max_size);
+}
+
+fn mutate_node(node: &mut Value, rng: &mut Rng, config: &Config) {
+    match node {
+        Value::Array(values) if !values.is_empty() && rng.next() % 2 == 1 => {
+            let index = rng.next() % values.len();
+            match rng.next() % 3 {
+                0 => drop(values.remove(index)),
+                1 => values.insert(index, values[index].clone()),
+                _ => values.insert(index, fresh(rng, config)),
+            }
+        }
+        Value::Object(pairs) if !pairs.is_empty() && rng.next() % 2 == 1 => {
+            let index = rng.next() % pairs.len();
+            match rng.next() % 3 {
+                0 => drop(pairs.remove(index)),
+                // Duplicate keys are valid JSON that parsers handle differently
+                1 => pairs.insert(index, pairs[index].clone()),
+                _ => {
+                    let mut scratch = Vec::new();
+                    let mut depth = config.max_depth.saturating_sub(FRESH_DEPTH);
+                    let key =
+                        match JsonString::generate_value(rng, config, &mut depth, &mut scratch) {
+                            Value::String(key) => key,
+                            _ => unreachable!(),
+                        };
+                    pairs.insert(index, (key, fresh(rng, config)));
+                }
+            }
+        }
+        _ => *node = fresh(rng, config),
+    }
+}
+
+/// Fresh value from the grammar
+fn fresh(rng: &mut Rng, config: &Config) -> Value {
+    let mut depth = config.max_depth.saturating_sub(FRESH_DEPTH);
+    JsonValue::generate_value(rng, config, &mut depth, &mut Vec::new())
+}
+
+/// Number of values in `doc`, itself included
+pub fn count(doc: &Value) -> usize {
+    1 + match doc {
+        Value::Array(values) => values.iter().map(count).sum(),
+        Value::Object(pairs) => pairs.iter().map(|(_, value)| count(value)).sum(),
+        _ => 0,
+    }
+}
+
+/// Value number `index` of `doc` in pre-order, `doc` itself being 0
+fn nth(doc: &Value, index: usize) -> &Value {
+    if index == 0 {
+        return doc;
+    }
+    let mut index = index - 1;
+    let children: Box<dyn Iterator<Item = &Value>> = match doc {
+        Value::Array(values) => Box::new(values.iter()),
+        Value::Object(pairs) => Box::new(pairs.iter().map(|(_, value)| value)),
+        _ => unreachable!("index past the last value"),
+    };
+    for child in children {
+        let size = count(child);
+        if index < size {
+            return nth(child, index);
+        }
+        index -= size;
+    }
+    unreachable!("index past the last value")
+}
+
+/// Mutable `nth`
+fn nth_mut(doc: &mut Value, index: usize) -> &mut Value {
+    if index == 0 {
+        return doc;
+    }
+    let mut index = index - 1;
+    let children: Box<dyn Iterator<Item = &mut Value>> = match doc {
+        Value::Array(values) => Box::new(values.iter_mut()),
+        Value::Object(pairs) => Box::new(pairs.iter_mut().map(|(_, value)| value)),
+        _ => unreachable!("index past the last value"),
+    };
+    for child in children {
+        let size = count(child);
+        if index < size {
+            return nth_mut(child, index);
+        }
+        index -= size;
+    }
+    unreachable!("index past the last value")
+}
+
+/// Remove value number `index` of `doc` in pre-order from its array or
+/// object. `index` can't be 0, `doc` itself has no parent.
+pub fn remove(doc: &mut Value, index: usize) {
+    assert!(index > 0, "the root has no parent");
+    let mut index = index - 1;
+    let mut children: Vec<&mut Value> = match doc {
+        Value::Array(values) => values.iter_mut().collect(),
+        Value::Object(pairs) => pairs.iter_mut().map(|(_, value)| value).collect(),
+        _ => unreachable!("index past the last value"),
+    };
+    for i in 0..children.len() {
+        let size = count(children[i]);
+        if index == 0 {
+            match doc {
+                Value::Array(values) => drop(values.remove(i)),
+                Value::Object(pairs) => drop(pairs.remove(i)),
+                _ => unreachable!(),
+            }
+            return;
+        }
+        if index < size {
+            return remove(children.swap_remove(i), index);
+        }
+        index -= size;
+    }
+    unreachable!("index past the last value")
+}
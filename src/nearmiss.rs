@@ -0,0 +1,357 @@
+//! Near-miss invalid inputs for the `json4` grammar
+//!
+//! Generates a derivation and perturbs it at exactly one terminal: a
+//! required token dropped, a separator duplicated, a terminal swapped for one
+//! of another rule, or an illegal character inside a `JsonString` or
+//! `Number`. The rest of the input stays valid, so parsers get far enough to
+//! exercise their error paths instead of bailing out on the first byte.
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::json4::Json;
+use crate::value::Value;
+use crate::{Config, Generate, Rng};
+
+/// Perturbation applied to a derivation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mutation {
+    /// `':'` of a `JsonPair` removed
+    DropColon,
+
+    /// Closing `']'` of a `JsonArray` or `'}'` of a `JsonObject` removed
+    DropClose,
+
+    /// Opening or closing `'"'` of a `JsonString` removed
+    DropQuote,
+
+    /// `','` doubled
+    DuplicateComma,
+
+    /// `','` added before the closing bracket of a non-empty container
+    TrailingComma,
+
+    /// Structural terminal replaced by one of another rule, e.g. `':'` by
+    /// `','`, `']'` by `'}'` or `'"'` by `'\''`
+    SwapTerminal,
+
+    /// Raw `\u0000..\u001f` inserted in a `JsonString`
+    ControlChar,
+
+    /// Escape outside `["\\/bfnrt]` or a `\u` with too few hex digits
+    BadEscape,
+
+    /// `Number` with a leading zero, a dangling `.` or `e`, or a `+` sign
+    BadNumber,
+
+    /// `true`, `false` or `null` truncated or with the wrong case
+    BadLiteral,
+}
+
+/// Number of derivations tried before giving up on the mutation
+const ATTEMPTS: usize = 16;
+
+/// Rules that generate a near-miss of their language
+pub trait GenerateNearMiss {
+    /// Generate an invalid input one mutation away from a valid derivation,
+    /// returning the mutation applied
+    ///
+    /// Returns `None` if every mutation tried still parsed, in which case
+    /// `buf` holds the last candidate.
+    fn generate_near_miss(
+        rng: &mut Rng,
+        config: &Config,
+        depth: &mut u64,
+        buf: &mut Vec<u8>,
+    ) -> Option<Mutation>;
+}
+
+impl GenerateNearMiss for Json {
+    fn generate_near_miss(
+        rng: &mut Rng,
+        config: &Config,
+        depth: &mut u64,
+        buf: &mut Vec<u8>,
+    ) -> Option<Mutation> {
+        near_miss(rng, config, depth, buf, &mut mutate)
+    }
+}
+
+/// `generate_near_miss` of `Json` with the mutations applied by `mutate`
+fn near_miss(
+    rng: &mut Rng,
+    config: &Config,
+    depth: &mut u64,
+    buf: &mut Vec<u8>,
+    mutate: &mut dyn FnMut(&mut Rng, &mut Vec<u8>) -> Mutation,
+) -> Option<Mutation> {
+    let start = buf.len();
+    let start_depth = *depth;
+    for _ in 0..ATTEMPTS {
+        buf.truncate(start);
+
+        // Every attempt gets the whole depth budget
+        *depth = start_depth;
+        Json::generate(rng, config, depth, buf);
+        let mut doc = buf.split_off(start);
+        let mutation = mutate(rng, &mut doc);
+        buf.extend_from_slice(&doc);
+
+        // Only keep mutations that really broke the derivation
+        if Value::parse(&buf[start..]).is_err() {
+            return Some(mutation);
+        }
+    }
+    None
+}
+
+/// Terminals of a derivation, as byte offsets into it
+#[derive(Default)]
+struct Sites {
+    colons: Vec<usize>,
+    commas: Vec<usize>,
+    opens: Vec<usize>,
+
+    /// Closing brackets, and whether their container is non-empty
+    closes: Vec<(usize, bool)>,
+
+    /// `JsonString` spans, quotes included
+    strings: Vec<(usize, usize)>,
+    numbers: Vec<(usize, usize)>,
+    literals: Vec<(usize, usize)>,
+}
+
+impl Sites {
+    /// Lex a valid, compact JSON document
+    fn find(doc: &[u8]) -> Sites {
+        let mut sites = Sites::default();
+        let mut pos = 0;
+        while pos < doc.len() {
+            let start = pos;
+            match doc[pos] {
+                b':' => sites.colons.push(pos),
+                b',' => sites.commas.push(pos),
+                b'[' | b'{' => sites.opens.push(pos),
+                b']' | b'}' => {
+                    let non_empty = !matches!(doc[pos - 1], b'[' | b'{');
+                    sites.closes.push((pos, non_empty));
+                }
+                b'"' => {
+                    pos += 1;
+                    while doc[pos] != b'"' {
+                        pos += if doc[pos] == b'\\' { 2 } else { 1 };
+                    }
+                    sites.strings.push((start, pos + 1));
+                }
+                b't' | b'f' | b'n' => {
+                    while pos + 1 < doc.len() && doc[pos + 1].is_ascii_lowercase() {
+                        pos += 1;
+                    }
+                    sites.literals.push((start, pos + 1));
+                }
+                _ => {
+                    while pos + 1 < doc.len() && b"0123456789.eE+-".contains(&doc[pos + 1]) {
+                        pos += 1;
+                    }
+                    sites.numbers.push((start, pos + 1));
+                }
+            }
+            pos += 1;
+        }
+        sites
+    }
+}
+
+fn pick<T: Copy>(rng: &mut Rng, items: &[T]) -> T {
+    items[rng.next() % items.len()]
+}
+
+/// Offsets inside the string at `start..end` where a character can be
+/// inserted without splitting an escape or a UTF-8 sequence
+fn boundaries(doc: &[u8], start: usize, end: usize) -> Vec<usize> {
+    let mut res = vec![start + 1];
+    let mut pos = start + 1;
+    while pos < end - 1 {
+        pos += match doc[pos] {
+            b'\\' if doc[pos + 1] == b'u' => 6,
+            b'\\' => 2,
+            c if c < 0x80 => 1,
+            c if c < 0xe0 => 2,
+            c if c < 0xf0 => 3,
+            _ => 4,
+        };
+        res.push(pos);
+    }
+    res
+}
+
+/// Apply one randomly chosen applicable mutation to `doc`
+fn mutate(rng: &mut Rng, doc: &mut Vec<u8>) -> Mutation {
+    let sites = Sites::find(doc);
+    let non_empty: Vec<usize> = sites
+        .closes
+        .iter()
+        .filter(|(_, non_empty)| *non_empty)
+        .map(|&(pos, _)| pos)
+        .collect();
+
+    let mut options = Vec::new();
+    if !sites.colons.is_empty() {
+        options.push(Mutation::DropColon);
+    }
+    if !sites.closes.is_empty() {
+        options.push(Mutation::DropClose);
+        options.push(Mutation::SwapTerminal);
+    }
+    if !sites.commas.is_empty() {
+        options.push(Mutation::DuplicateComma);
+    }
+    if !non_empty.is_empty() {
+        options.push(Mutation::TrailingComma);
+    }
+    if !sites.strings.is_empty() {
+        options.push(Mutation::DropQuote);
+        options.push(Mutation::ControlChar);
+        options.push(Mutation::BadEscape);
+    }
+    if !sites.numbers.is_empty() {
+        options.push(Mutation::BadNumber);
+    }
+    if !sites.literals.is_empty() {
+        options.push(Mutation::BadLiteral);
+    }
+
+    let mutation = pick(rng, &options);
+    match mutation {
+        Mutation::DropColon => {
+            doc.remove(pick(rng, &sites.colons));
+        }
+        Mutation::DropClose => {
+            doc.remove(pick(rng, &sites.closes).0);
+        }
+        Mutation::DropQuote => {
+            let (start, end) = pick(rng, &sites.strings);
+            doc.remove(if rng.next() % 2 == 1 { start } else { end - 1 });
+        }
+        Mutation::DuplicateComma => doc.insert(pick(rng, &sites.commas), b','),
+        Mutation::TrailingComma => doc.insert(pick(rng, &non_empty), b','),
+        Mutation::SwapTerminal => {
+            let mut terminals: Vec<usize> = sites.closes.iter().map(|&(pos, _)| pos).collect();
+            terminals.extend(&sites.opens);
+            terminals.extend(&sites.colons);
+            terminals.extend(&sites.commas);
+            terminals.extend(sites.strings.iter().map(|&(start, _)| start));
+            let pos = pick(rng, &terminals);
+            doc[pos] = match doc[pos] {
+                b':' => b',',
+                b',' => b':',
+                b'[' => b'{',
+                b'{' => b'[',
+                b']' => b'}',
+                b'}' => b']',
+                b'"' => b'\'',
+                _ => unreachable!(),
+            };
+        }
+        Mutation::ControlChar => {
+            let (start, end) = pick(rng, &sites.strings);
+            let pos = pick(rng, &boundaries(doc, start, end));
+            doc.insert(pos, (rng.next() % 0x20) as u8);
+        }
+        Mutation::BadEscape => {
+            let (start, end) = pick(rng, &sites.strings);
+            let pos = pick(rng, &boundaries(doc, start, end));
+            let escapes: [&[u8]; 6] = [b"\\x", b"\\a", b"\\0", b"\\U", b"\\u12", b"\\u0G00"];
+            doc.splice(pos..pos, pick(rng, &escapes).iter().copied());
+        }
+        Mutation::BadNumber => {
+            let (start, end) = pick(rng, &sites.numbers);
+            let int_start = if doc[start] == b'-' { start + 1 } else { start };
+            match rng.next() % 4 {
+                // Leading zero
+                0 => doc.insert(int_start, b'0'),
+                // Fraction without digits
+                1 => {
+                    let int_end = (int_start..end)
+                        .find(|&pos| !doc[pos].is_ascii_digit())
+                        .unwrap_or(end);
+                    doc.splice(int_start..end, doc[int_start..int_end].to_vec());
+                    doc.insert(int_end, b'.');
+                }
+                // Exponent without digits
+                2 => doc.insert(end, b'e'),
+                // Explicit plus
+                3 => doc.insert(int_start, b'+'),
+                _ => unreachable!(),
+            }
+        }
+        Mutation::BadLiteral => {
+            let (start, end) = pick(rng, &sites.literals);
+            if rng.next() % 2 == 1 {
+                doc.remove(end - 1);
+            } else {
+                doc[start] = doc[start].to_ascii_uppercase();
+            }
+        }
+    }
+    mutation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_mutations_that_still_parse() {
+        // Nothing breaks a document by adding whitespace
+        let mut attempts = 0;
+        let mut pad = |_: &mut Rng, doc: &mut Vec<u8>| {
+            attempts += 1;
+            doc.push(b' ');
+            Mutation::DropColon
+        };
+        let mut buf = b"prefix".to_vec();
+        let rng = &mut Rng::seeded(0);
+        let config = Config::default();
+        assert_eq!(near_miss(rng, &config, &mut 0, &mut buf, &mut pad), None);
+        assert_eq!(attempts, ATTEMPTS);
+
+        // The last candidate is left after what was already in `buf`
+        assert!(buf.starts_with(b"prefix"));
+        assert!(Value::parse(&buf[6..]).is_ok());
+    }
+
+    #[test]
+    fn keeps_first_breaking_mutation() {
+        let mut attempts = 0;
+        let mut third = |_: &mut Rng, doc: &mut Vec<u8>| {
+            attempts += 1;
+            if attempts == 3 {
+                doc.push(b',');
+            }
+            Mutation::TrailingComma
+        };
+        let mut buf = Vec::new();
+        let rng = &mut Rng::seeded(0);
+        let config = Config::default();
+        let mutation = near_miss(rng, &config, &mut 0, &mut buf, &mut third);
+        assert_eq!(mutation, Some(Mutation::TrailingComma));
+        assert_eq!(attempts, 3);
+        assert!(Value::parse(&buf).is_err());
+    }
+
+    #[test]
+    fn every_mutation_breaks() {
+        let mut seen = Vec::new();
+        for seed in 0..2000 {
+            let mut buf = Vec::new();
+            let rng = &mut Rng::seeded(seed);
+            let mutation = Json::generate_near_miss(rng, &Config::default(), &mut 0, &mut buf)
+                .expect("no near-miss");
+            assert!(Value::parse(&buf).is_err(), "seed {}", seed);
+            if !seen.contains(&mutation) {
+                seen.push(mutation);
+            }
+        }
+        assert_eq!(seen.len(), 10, "{:?}", seen);
+    }
+}
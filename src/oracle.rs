@@ -0,0 +1,61 @@
+//! Semantic oracle for the `json4` grammar
+//!
+//! Generates JSON together with the value it represents, so a harness can
+//! parse the bytes with its target and compare the result against the
+//! `Value` instead of only watching for crashes. This catches wrong escape
+//! decoding, number rounding and similar silent mis-parses.
+//!
+//! The inputs are the ones of the `json4` rules for the same seed, decoded
+//! by `Value::parse`. A `\u` escape of a lone surrogate has no decoded
+//! value, so it is expected as U+FFFD like most lenient parsers decode it.
+use alloc::vec::Vec;
+
+use crate::json4::*;
+use crate::value::Value;
+use crate::{Config, Generate, Rng};
+
+/// Rules that also return the value of what they generated
+pub trait GenerateValue: Generate {
+    fn generate_value(rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut Vec<u8>) -> Value {
+        let start = buf.len();
+        Self::generate(rng, config, depth, buf);
+
+        // Every implementor generates a complete JSON text
+        Value::parse(&buf[start..]).unwrap()
+    }
+}
+
+impl GenerateValue for Json {}
+impl GenerateValue for JsonValue {}
+impl GenerateValue for JsonObject {}
+impl GenerateValue for JsonArray {}
+impl GenerateValue for JsonString {}
+impl GenerateValue for Number {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_inputs_as_json4() {
+        for key_mode in [KeyMode::Random, KeyMode::Unique, KeyMode::Duplicate(40)] {
+            let config = Config {
+                key_mode,
+                ..Config::default()
+            };
+            for seed in 0..200 {
+                let (mut a, mut b) = (Vec::new(), Vec::new());
+                let (mut depth_a, mut depth_b) = (0, 0);
+                Json::generate(&mut Rng::seeded(seed), &config, &mut depth_a, &mut a);
+                let value =
+                    Json::generate_value(&mut Rng::seeded(seed), &config, &mut depth_b, &mut b);
+                assert!(a == b && depth_a == depth_b, "seed {}", seed);
+
+                // The expected value survives a round trip
+                let mut written = Vec::new();
+                value.write(&mut written);
+                assert_eq!(Value::parse(&written), Ok(value));
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Shared-memory ring buffer of generated inputs
+//!
+//! The producer maps a file, usually in `/dev/shm`, and appends inputs to it
+//! for a consumer in another process, such as a fuzzer or a hypervisor-based
+//! harness, to read in place without copies or syscalls. There is one
+//! producer and one consumer per ring.
+//!
+//! The file starts with a header, all fields little-endian:
+//!
+//! | Offset | Size | Field                                                  |
+//! |--------|------|--------------------------------------------------------|
+//! | 0      | 8    | Magic `b"BNFRING\0"`, written last by the producer     |
+//! | 8      | 4    | Version, currently 1                                   |
+//! | 12     | 4    | Offset of the data area in the file                    |
+//! | 16     | 8    | Capacity of the data area in bytes, a multiple of 8    |
+//! | 64     | 8    | Write cursor: bytes ever written to the data area      |
+//! | 128    | 8    | Read cursor: bytes ever consumed from the data area    |
+//! | 192    | 4    | Closed: 1 once the producer wrote its last input       |
+//!
+//! The cursors only grow, so `write - read` is the number of bytes in use and
+//! a cursor `c` points at `data + c % capacity`. The producer owns the write
+//! cursor and the consumer the read cursor, each on its own cache line. Both
+//! are accessed atomically, with release stores after the data they cover is
+//! written or consumed and acquire loads before touching the data.
+//!
+//! Each input is a record of its length as a `u32`, its bytes and zero
+//! padding to a multiple of 8 bytes. Records never wrap around the end of the
+//! data area: when the next one doesn't fit, the producer writes the length
+//! `0xffffffff` and continues at the start of the data area.
+use std::ffi::c_void;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+pub const MAGIC: u64 = u64::from_le_bytes(*b"BNFRING\0");
+pub const VERSION: u32 = 1;
+
+/// Capacity of the data area of new rings
+pub const CAPACITY: u64 = 64 << 20;
+
+const DATA_OFFSET: u32 = 256;
+const WRITE_CURSOR: usize = 64;
+const READ_CURSOR: usize = 128;
+const CLOSED: usize = 192;
+
+/// Length marking the end of a lap of the data area
+const WRAP: u32 = u32::MAX;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const MAP_SHARED: i32 = 1;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, off: i64)
+        -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+/// Shared mapping of a whole ring file
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is only accessed through atomics and the cursor protocol
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    fn new(file: &File) -> io::Result<Mapping> {
+        let len = file.metadata()?.len() as usize;
+        let ptr = unsafe {
+            mmap(
+                core::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> &AtomicU32 {
+        assert!(offset.is_multiple_of(4) && offset + 4 <= self.len);
+        unsafe { &*(self.ptr.add(offset) as *const AtomicU32) }
+    }
+
+    fn u64_at(&self, offset: usize) -> &AtomicU64 {
+        assert!(offset.is_multiple_of(8) && offset + 8 <= self.len);
+        unsafe { &*(self.ptr.add(offset) as *const AtomicU64) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { munmap(self.ptr as *mut c_void, self.len) };
+    }
+}
+
+/// Size of the record holding an input of `len` bytes
+fn record_size(len: usize) -> u64 {
+    (4 + len as u64 + 7) & !7
+}
+
+/// Producing end of a ring
+pub struct RingWriter {
+    map: Mapping,
+    capacity: u64,
+
+    /// Write cursor, only ever changed by us
+    write: u64,
+}
+
+impl RingWriter {
+    /// Create the ring file at `path` with a data area of `capacity` bytes,
+    /// replacing any previous one
+    pub fn create(path: &Path, capacity: u64) -> io::Result<RingWriter> {
+        let capacity = capacity & !15;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(DATA_OFFSET as u64 + capacity)?;
+
+        let map = Mapping::new(&file)?;
+        map.u32_at(8).store(VERSION, Ordering::Relaxed);
+        map.u32_at(12).store(DATA_OFFSET, Ordering::Relaxed);
+        map.u64_at(16).store(capacity, Ordering::Relaxed);
+        map.u64_at(0).store(MAGIC, Ordering::Release);
+        Ok(RingWriter {
+            map,
+            capacity,
+            write: 0,
+        })
+    }
+
+    /// Largest input that fits in the ring
+    ///
+    /// Half the capacity, so that there is room for it either before the end
+    /// of the data area or after its start, wherever the write cursor is.
+    pub fn max_input(&self) -> usize {
+        self.capacity as usize / 2 - 4
+    }
+
+    /// Append `input`, returning `false` if the ring is too full for it
+    pub fn try_push(&mut self, input: &[u8]) -> bool {
+        assert!(input.len() <= self.max_input());
+        let size = record_size(input.len());
+        let pos = self.write % self.capacity;
+        let tail = self.capacity - pos;
+        let needed = if size > tail { tail + size } else { size };
+
+        let read = self.map.u64_at(READ_CURSOR).load(Ordering::Acquire);
+        if self.write + needed - read > self.capacity {
+            return false;
+        }
+
+        let mut pos = pos;
+        if size > tail {
+            self.map
+                .u32_at(DATA_OFFSET as usize + pos as usize)
+                .store(WRAP, Ordering::Relaxed);
+            self.write += tail;
+            pos = 0;
+        }
+
+        let offset = DATA_OFFSET as usize + pos as usize;
+        self.map
+            .u32_at(offset)
+            .store(input.len() as u32, Ordering::Relaxed);
+        unsafe {
+            let dst = self.map.ptr.add(offset + 4);
+            core::ptr::copy_nonoverlapping(input.as_ptr(), dst, input.len());
+            let padding = size as usize - 4 - input.len();
+            core::ptr::write_bytes(dst.add(input.len()), 0, padding);
+        }
+        self.write += size;
+        self.map
+            .u64_at(WRITE_CURSOR)
+            .store(self.write, Ordering::Release);
+        true
+    }
+}
+
+impl Drop for RingWriter {
+    fn drop(&mut self) {
+        self.map.u32_at(CLOSED).store(1, Ordering::Release);
+    }
+}
+
+/// Consuming end of a ring, reading the inputs in place
+pub struct RingReader {
+    map: Mapping,
+    capacity: u64,
+
+    /// Read cursor past the input returned last, published on the next call
+    read: u64,
+}
+
+impl RingReader {
+    /// Attach to the ring at `path`
+    ///
+    /// Fails with `ErrorKind::WouldBlock` if the producer hasn't finished
+    /// setting it up yet.
+    pub fn open(path: &Path) -> io::Result<RingReader> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        if file.metadata()?.len() < DATA_OFFSET as u64 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let map = Mapping::new(&file)?;
+        match map.u64_at(0).load(Ordering::Acquire) {
+            MAGIC => {}
+            0 => return Err(io::ErrorKind::WouldBlock.into()),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a ring")),
+        }
+        if map.u32_at(8).load(Ordering::Relaxed) != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported ring version",
+            ));
+        }
+        let capacity = map.u64_at(16).load(Ordering::Relaxed);
+        if map.u32_at(12).load(Ordering::Relaxed) != DATA_OFFSET
+            || map.len as u64 != DATA_OFFSET as u64 + capacity
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "corrupt ring header",
+            ));
+        }
+
+        let read = map.u64_at(READ_CURSOR).load(Ordering::Acquire);
+        Ok(RingReader {
+            map,
+            capacity,
+            read,
+        })
+    }
+
+    /// Offset in the mapping and length of the next input, `None` once the
+    /// producer closed the ring and every input was read
+    fn advance(&mut self) -> io::Result<Option<(usize, usize)>> {
+        // Hand the previous input back to the producer
+        self.map
+            .u64_at(READ_CURSOR)
+            .store(self.read, Ordering::Release);
+
+        loop {
+            let closed = self.map.u32_at(CLOSED).load(Ordering::Acquire) == 1;
+            let write = self.map.u64_at(WRITE_CURSOR).load(Ordering::Acquire);
+            if self.read == write {
+                return match closed {
+                    true => Ok(None),
+                    false => Err(io::ErrorKind::WouldBlock.into()),
+                };
+            }
+
+            let pos = self.read % self.capacity;
+            let offset = DATA_OFFSET as usize + pos as usize;
+            let len = self.map.u32_at(offset).load(Ordering::Relaxed);
+            if len == WRAP {
+                self.read += self.capacity - pos;
+                continue;
+            }
+            if len as u64 + 4 > self.capacity - pos {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "corrupt ring record",
+                ));
+            }
+
+            self.read += record_size(len as usize);
+            return Ok(Some((offset + 4, len as usize)));
+        }
+    }
+
+    fn input(&self, (offset, len): (usize, usize)) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.map.ptr.add(offset), len) }
+    }
+
+    /// Next input, or `None` once the producer closed the ring and every
+    /// input was read. Fails with `ErrorKind::WouldBlock` instead of waiting
+    /// when no input is ready.
+    ///
+    /// The slice points into the ring and stays valid until the next call.
+    pub fn try_next(&mut self) -> io::Result<Option<&[u8]>> {
+        match self.advance()? {
+            Some(next) => Ok(Some(self.input(next))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path of a ring file unique to this process and test
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bnf-ring-{}-{}", std::process::id(), name))
+    }
+
+    fn next(reader: &mut RingReader) -> Option<Vec<u8>> {
+        reader.try_next().unwrap().map(|input| input.to_vec())
+    }
+
+    #[test]
+    fn full_and_wrap_around() {
+        let path = path("wrap");
+        let mut writer = RingWriter::create(&path, 64).unwrap();
+        let mut reader = RingReader::open(&path).unwrap();
+        let err = reader.try_next().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        // Records of 24 bytes, two of which fit before the end of the data
+        let [a, b, c, d] = [[b'a'; 20], [b'b'; 20], [b'c'; 20], [b'd'; 20]];
+        assert!(writer.try_push(&a) && writer.try_push(&b));
+        assert!(!writer.try_push(&c), "wrapped over unread data");
+        assert_eq!(next(&mut reader).unwrap(), a);
+        assert_eq!(next(&mut reader).unwrap(), b);
+
+        // `a` is handed back once `b` is read, which is room enough to wrap
+        assert!(writer.try_push(&c));
+        assert!(!writer.try_push(&d));
+        assert_eq!(next(&mut reader).unwrap(), c);
+        assert!(writer.try_push(&d));
+        assert_eq!(next(&mut reader).unwrap(), d);
+
+        drop(writer);
+        assert_eq!(next(&mut reader), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn many_laps() {
+        let path = path("laps");
+        let mut writer = RingWriter::create(&path, 1024).unwrap();
+        let mut reader = RingReader::open(&path).unwrap();
+        let inputs: Vec<Vec<u8>> = (0..2000)
+            .map(|i| vec![i as u8; i * 7 % (writer.max_input() + 1)])
+            .collect();
+
+        // Read only when the ring is full, so every lap ends full
+        let mut read = 0;
+        for input in &inputs {
+            while !writer.try_push(input) {
+                match reader.try_next() {
+                    Ok(Some(input)) => {
+                        assert_eq!(input, &inputs[read][..]);
+                        read += 1;
+                    }
+                    // Everything was read, and the last input is handed back
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                    other => panic!("{:?}", other),
+                }
+            }
+        }
+        drop(writer);
+        while let Some(input) = next(&mut reader) {
+            assert_eq!(input, inputs[read]);
+            read += 1;
+        }
+        assert_eq!(read, inputs.len());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -6,11 +6,42 @@ pub struct Rng {
     value: u128,
 }
 
+#[cfg(any(target_arch = "x86_64", feature = "std"))]
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::new()
+    }
+}
+
 impl Rng {
+    /// Rng seeded from the clock, see `clock_seed`
+    #[cfg(any(target_arch = "x86_64", feature = "std"))]
     pub fn new() -> Rng {
-        let mut res = Rng {
-            value: unsafe { core::arch::x86_64::_rdtsc() } as u128,
+        Rng::warm_up(crate::clock_seed() as u128)
+    }
+
+    /// Rng producing the same sequence every time for a given `seed`
+    ///
+    /// The seed is spread over the whole state with splitmix64, so nearby
+    /// seeds such as `0, 1, 2` give unrelated sequences.
+    pub fn seeded(seed: u64) -> Rng {
+        let mut state = seed;
+        let mut splitmix = || {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
         };
+        let high = splitmix() as u128;
+        let low = splitmix() as u128;
+
+        // An odd state keeps the generator on its longest cycle
+        Rng::warm_up(high << 64 | low | 1)
+    }
+
+    fn warm_up(value: u128) -> Rng {
+        let mut res = Rng { value };
 
         // Cycle through to create some chaos
         for _ in 0..100 {
@@ -20,8 +51,24 @@ impl Rng {
         res
     }
 
+    // Never runs out, unlike `Iterator::next`
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> usize {
         self.value = self.value.wrapping_mul(0xda942042e4dd58b5);
         (self.value >> 64) as usize
     }
+
+    /// Index into `weights` picked with probability proportional to its
+    /// weight. The weights can't all be zero.
+    pub fn weighted(&mut self, weights: &[u32]) -> usize {
+        let total: u64 = weights.iter().map(|&w| w as u64).sum();
+        let mut pick = self.next() as u64 % total;
+        for (i, &weight) in weights.iter().enumerate() {
+            if pick < weight as u64 {
+                return i;
+            }
+            pick -= weight as u64;
+        }
+        unreachable!()
+    }
 }
@@ -0,0 +1,1096 @@
+//! JSON Schema guided generation
+//!
+//! Generates instances of a JSON Schema document, or instances violating
+//! exactly one of its constraints. Supported keywords are `type`,
+//! `properties`, `required`, `enum`, `minimum`, `maximum`, `minLength`,
+//! `maxLength`, `pattern`, `items`, `minItems`, `maxItems` and `oneOf`.
+//! Other keywords are ignored.
+//!
+//! Parts of the schema without constraints are filled in by the `json4`
+//! rules. Every candidate is checked against the schema and regenerated a
+//! few times if it doesn't fit, since keywords can interact (`oneOf`
+//! branches overlapping, `pattern` against `maxLength`, ...).
+use core::fmt;
+
+use crate::json4::{JsonArray, JsonObject, JsonString, JsonValue, Number};
+use crate::value::{write_string, ParseError, Value};
+use crate::{Config, Generate, Rng};
+
+/// Number of candidates tried before settling for one that doesn't fit
+const ATTEMPTS: usize = 16;
+
+/// Error returned for schema documents that can't be used
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaError {
+    /// The document isn't valid JSON
+    Parse(ParseError),
+
+    /// The named keyword has a value of the wrong shape
+    Keyword(&'static str),
+
+    /// `pattern` uses syntax outside the supported subset
+    Pattern(String),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::Parse(err) => write!(f, "schema is not valid JSON: {}", err),
+            SchemaError::Keyword(keyword) => write!(f, "unsupported value for `{}`", keyword),
+            SchemaError::Pattern(pattern) => write!(f, "unsupported pattern `{}`", pattern),
+        }
+    }
+}
+
+/// Instance types of the `type` keyword
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Null,
+    Boolean,
+    Integer,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl Type {
+    fn from_name(name: &str) -> Option<Type> {
+        Some(match name {
+            "null" => Type::Null,
+            "boolean" => Type::Boolean,
+            "integer" => Type::Integer,
+            "number" => Type::Number,
+            "string" => Type::String,
+            "array" => Type::Array,
+            "object" => Type::Object,
+            _ => return None,
+        })
+    }
+
+    fn matches(self, value: &Value) -> bool {
+        match (self, value) {
+            (Type::Null, Value::Null)
+            | (Type::Boolean, Value::Bool(_))
+            | (Type::Number, Value::Number(_))
+            | (Type::String, Value::String(_))
+            | (Type::Array, Value::Array(_))
+            | (Type::Object, Value::Object(_)) => true,
+            (Type::Integer, Value::Number(_)) => value.as_f64().is_some_and(|n| n.fract() == 0.),
+            _ => false,
+        }
+    }
+}
+
+/// A single way of breaking a schema
+#[derive(Debug, Clone, Copy)]
+enum Violation {
+    Type,
+    Enum,
+    Minimum,
+    Maximum,
+    MinLength,
+    MaxLength,
+    Pattern,
+    Required,
+    MinItems,
+    MaxItems,
+    Property(usize),
+    Items,
+    OneOf,
+}
+
+/// Parsed JSON Schema
+#[derive(Debug, Default)]
+pub struct Schema {
+    /// Allowed types, any type if empty
+    types: Vec<Type>,
+    properties: Vec<(String, Schema)>,
+    required: Vec<String>,
+    enumeration: Option<Vec<Value>>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    min_length: usize,
+    max_length: Option<usize>,
+    pattern: Option<Pattern>,
+    items: Option<Box<Schema>>,
+    min_items: usize,
+    max_items: Option<usize>,
+    one_of: Vec<Schema>,
+}
+
+impl Schema {
+    /// Parse a schema from the bytes of a JSON Schema document
+    pub fn parse(input: &[u8]) -> Result<Schema, SchemaError> {
+        let value = Value::parse(input).map_err(SchemaError::Parse)?;
+        Schema::from_value(&value)
+    }
+
+    /// Build a schema from an already parsed document
+    pub fn from_value(value: &Value) -> Result<Schema, SchemaError> {
+        let mut schema = Schema::default();
+
+        // `true` accepts everything, `false` nothing, which can't be generated
+        let pairs = match value {
+            Value::Bool(true) => return Ok(schema),
+            Value::Object(pairs) => pairs,
+            _ => return Err(SchemaError::Keyword("schema")),
+        };
+
+        for (keyword, value) in pairs {
+            match keyword.as_str() {
+                "type" => {
+                    let names = match value {
+                        Value::String(name) => vec![name.as_str()],
+                        Value::Array(names) => names
+                            .iter()
+                            .map(|name| name.as_str().ok_or(SchemaError::Keyword("type")))
+                            .collect::<Result<_, _>>()?,
+                        _ => return Err(SchemaError::Keyword("type")),
+                    };
+                    for name in names {
+                        schema
+                            .types
+                            .push(Type::from_name(name).ok_or(SchemaError::Keyword("type"))?);
+                    }
+                }
+                "properties" => match value {
+                    Value::Object(properties) => {
+                        for (name, property) in properties {
+                            schema
+                                .properties
+                                .push((name.clone(), Schema::from_value(property)?));
+                        }
+                    }
+                    _ => return Err(SchemaError::Keyword("properties")),
+                },
+                "required" => {
+                    for name in value.as_array().ok_or(SchemaError::Keyword("required"))? {
+                        let name = name.as_str().ok_or(SchemaError::Keyword("required"))?;
+                        schema.required.push(name.to_string());
+                    }
+                }
+                "enum" => {
+                    let values = value.as_array().ok_or(SchemaError::Keyword("enum"))?;
+                    schema.enumeration = Some(values.to_vec());
+                }
+                "minimum" => {
+                    let min = value.as_f64().ok_or(SchemaError::Keyword("minimum"))?;
+                    schema.minimum = Some(min.clamp(f64::MIN, f64::MAX));
+                }
+                "maximum" => {
+                    let max = value.as_f64().ok_or(SchemaError::Keyword("maximum"))?;
+                    schema.maximum = Some(max.clamp(f64::MIN, f64::MAX));
+                }
+                "minLength" => {
+                    schema.min_length =
+                        value.as_usize().ok_or(SchemaError::Keyword("minLength"))?;
+                }
+                "maxLength" => {
+                    let max = value.as_usize().ok_or(SchemaError::Keyword("maxLength"))?;
+                    schema.max_length = Some(max);
+                }
+                "pattern" => {
+                    let pattern = value.as_str().ok_or(SchemaError::Keyword("pattern"))?;
+                    schema.pattern = Some(Pattern::parse(pattern)?);
+                }
+                "items" => {
+                    // The tuple form of older drafts isn't supported
+                    schema.items = Some(Box::new(Schema::from_value(value)?));
+                }
+                "minItems" => {
+                    schema.min_items = value.as_usize().ok_or(SchemaError::Keyword("minItems"))?;
+                }
+                "maxItems" => {
+                    let max = value.as_usize().ok_or(SchemaError::Keyword("maxItems"))?;
+                    schema.max_items = Some(max);
+                }
+                "oneOf" => {
+                    for branch in value.as_array().ok_or(SchemaError::Keyword("oneOf"))? {
+                        schema.one_of.push(Schema::from_value(branch)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(schema)
+    }
+
+    /// Generate an instance conforming to the schema into `buf`
+    ///
+    /// Returns `false` if no conforming instance was found within a few
+    /// attempts, in which case `buf` holds the last candidate.
+    pub fn generate(
+        &self,
+        rng: &mut Rng,
+        config: &Config,
+        depth: &mut u64,
+        buf: &mut Vec<u8>,
+    ) -> bool {
+        let start = buf.len();
+        let start_depth = *depth;
+        for _ in 0..ATTEMPTS {
+            buf.truncate(start);
+
+            // Every attempt gets the whole depth budget
+            *depth = start_depth;
+            self.instance(rng, config, depth, buf);
+            if self.check(&buf[start..]) == Some(0) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Generate an instance violating exactly one constraint of the schema
+    ///
+    /// Returns `false` if no such instance was found within a few attempts,
+    /// in which case `buf` holds the last candidate.
+    pub fn generate_invalid(
+        &self,
+        rng: &mut Rng,
+        config: &Config,
+        depth: &mut u64,
+        buf: &mut Vec<u8>,
+    ) -> bool {
+        let start = buf.len();
+        let start_depth = *depth;
+        for _ in 0..ATTEMPTS {
+            buf.truncate(start);
+            *depth = start_depth;
+            self.invalid_instance(rng, config, depth, buf);
+            if self.check(&buf[start..]) == Some(1) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Number of violated constraints for the given bytes, `None` if they
+    /// aren't JSON at all
+    fn check(&self, input: &[u8]) -> Option<usize> {
+        Value::parse(input).ok().map(|value| self.errors(&value))
+    }
+
+    /// Number of constraints of the schema `value` violates
+    pub fn errors(&self, value: &Value) -> usize {
+        let mut errors = 0;
+
+        if !self.types.is_empty() && !self.types.iter().any(|ty| ty.matches(value)) {
+            errors += 1;
+        }
+
+        if let Some(values) = &self.enumeration {
+            if !values.iter().any(|v| json_eq(v, value)) {
+                errors += 1;
+            }
+        }
+
+        match value {
+            Value::Number(_) => {
+                let num = value.as_f64().unwrap_or(0.);
+                if self.minimum.is_some_and(|min| num < min) {
+                    errors += 1;
+                }
+                if self.maximum.is_some_and(|max| num > max) {
+                    errors += 1;
+                }
+            }
+            Value::String(s) => {
+                let len = s.chars().count();
+                if len < self.min_length {
+                    errors += 1;
+                }
+                if self.max_length.is_some_and(|max| len > max) {
+                    errors += 1;
+                }
+                if let Some(pattern) = &self.pattern {
+                    if !pattern.is_match(s) {
+                        errors += 1;
+                    }
+                }
+            }
+            Value::Array(values) => {
+                if values.len() < self.min_items {
+                    errors += 1;
+                }
+                if self.max_items.is_some_and(|max| values.len() > max) {
+                    errors += 1;
+                }
+                if let Some(items) = &self.items {
+                    errors += values.iter().map(|v| items.errors(v)).sum::<usize>();
+                }
+            }
+            Value::Object(pairs) => {
+                for name in &self.required {
+                    if !pairs.iter().any(|(key, _)| key == name) {
+                        errors += 1;
+                    }
+                }
+                for (key, v) in pairs {
+                    if let Some((_, property)) = self.properties.iter().find(|(k, _)| k == key) {
+                        errors += property.errors(v);
+                    }
+                }
+            }
+            Value::Null | Value::Bool(_) => {}
+        }
+
+        if !self.one_of.is_empty() {
+            let matching = self.one_of.iter().filter(|s| s.errors(value) == 0);
+            if matching.count() != 1 {
+                errors += 1;
+            }
+        }
+
+        errors
+    }
+
+    /// Type to generate, inferred from the other keywords if `type` is absent
+    fn pick_type(&self, rng: &mut Rng) -> Option<Type> {
+        if !self.types.is_empty() {
+            return Some(self.types[rng.next() % self.types.len()]);
+        }
+        if !self.properties.is_empty() || !self.required.is_empty() {
+            Some(Type::Object)
+        } else if self.items.is_some() || self.min_items > 0 || self.max_items.is_some() {
+            Some(Type::Array)
+        } else if self.pattern.is_some() || self.min_length > 0 || self.max_length.is_some() {
+            Some(Type::String)
+        } else if self.minimum.is_some() || self.maximum.is_some() {
+            Some(Type::Number)
+        } else {
+            None
+        }
+    }
+
+    /// Candidate instance, not checked against the schema
+    fn instance(&self, rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut Vec<u8>) {
+        *depth += 1;
+        if let Some(values) = &self.enumeration {
+            if !values.is_empty() {
+                return values[rng.next() % values.len()].write(buf);
+            }
+        }
+
+        if !self.one_of.is_empty() {
+            return self.one_of[rng.next() % self.one_of.len()].instance(rng, config, depth, buf);
+        }
+
+        self.typed_instance(rng, config, depth, buf);
+    }
+
+    /// Candidate instance following every keyword except `enum` and `oneOf`
+    fn typed_instance(&self, rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut Vec<u8>) {
+        match self.pick_type(rng) {
+            None => JsonValue::generate(rng, config, depth, buf),
+            Some(Type::Null) => buf.extend_from_slice(b"null"),
+            Some(Type::Boolean) => match rng.next() % 2 {
+                0 => buf.extend_from_slice(b"true"),
+                1 => buf.extend_from_slice(b"false"),
+                _ => unreachable!(),
+            },
+            Some(Type::Integer) => self.number(rng, true, buf),
+            Some(Type::Number) => {
+                if self.minimum.is_none() && self.maximum.is_none() {
+                    Number::generate(rng, config, depth, buf)
+                } else {
+                    self.number(rng, false, buf)
+                }
+            }
+            Some(Type::String) => self.string(rng, config, buf),
+            Some(Type::Array) => {
+                if self.items.is_none() && self.min_items == 0 && self.max_items.is_none() {
+                    return JsonArray::generate(rng, config, depth, buf);
+                }
+                let count = self.item_count(rng, config);
+                self.array(rng, config, depth, buf, count, None);
+            }
+            Some(Type::Object) => {
+                if self.properties.is_empty() && self.required.is_empty() {
+                    return JsonObject::generate(rng, config, depth, buf);
+                }
+                self.object(rng, config, depth, buf, None, None);
+            }
+        }
+    }
+
+    /// Candidate instance breaking one randomly chosen constraint
+    fn invalid_instance(&self, rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut Vec<u8>) {
+        *depth += 1;
+        let mut options = Vec::new();
+        if !self.types.is_empty() && !self.wrong_types().is_empty() {
+            options.push(Violation::Type);
+        }
+        if self.enumeration.is_some() {
+            options.push(Violation::Enum);
+        }
+        if self.minimum.is_some() {
+            options.push(Violation::Minimum);
+        }
+        if self.maximum.is_some() {
+            options.push(Violation::Maximum);
+        }
+        if self.min_length > 0 {
+            options.push(Violation::MinLength);
+        }
+        if self.max_length.is_some() {
+            options.push(Violation::MaxLength);
+        }
+        if self.pattern.is_some() {
+            options.push(Violation::Pattern);
+        }
+        if !self.required.is_empty() {
+            options.push(Violation::Required);
+        }
+        if self.min_items > 0 {
+            options.push(Violation::MinItems);
+        }
+        if self.max_items.is_some() {
+            options.push(Violation::MaxItems);
+        }
+        for i in 0..self.properties.len() {
+            options.push(Violation::Property(i));
+        }
+        if self.items.is_some() && self.max_items != Some(0) {
+            options.push(Violation::Items);
+        }
+        if !self.one_of.is_empty() {
+            options.push(Violation::OneOf);
+        }
+
+        // Nothing to break, `{}` accepts every instance
+        if options.is_empty() {
+            return self.instance(rng, config, depth, buf);
+        }
+
+        match options[rng.next() % options.len()] {
+            Violation::Type => self.wrong_type(rng, config, depth, buf),
+            // A fresh value of the right type is unlikely to be a member
+            Violation::Enum => self.typed_instance(rng, config, depth, buf),
+            Violation::Minimum => {
+                let min = self.minimum.unwrap();
+                let below = min - (1 + rng.next() % 100) as f64 * min.abs().max(1.);
+                write_number(below.floor(), buf);
+            }
+            Violation::Maximum => {
+                let max = self.maximum.unwrap();
+                let above = max + (1 + rng.next() % 100) as f64 * max.abs().max(1.);
+                write_number(above.ceil(), buf);
+            }
+            Violation::MinLength => {
+                let len = rng.next() % self.min_length;
+                write_string(&self.random_string(rng, len), buf);
+            }
+            Violation::MaxLength => {
+                let len = self.max_length.unwrap() + 1 + rng.next() % config.max_repeat;
+                write_string(&self.random_string(rng, len), buf);
+            }
+            Violation::Pattern => {
+                let len = self.min_length + rng.next() % config.max_repeat;
+                write_string(&self.random_string(rng, len), buf);
+            }
+            Violation::Required => {
+                let missing = rng.next() % self.required.len();
+                self.object(rng, config, depth, buf, Some(missing), None);
+            }
+            Violation::MinItems => {
+                let count = rng.next() % self.min_items;
+                self.array(rng, config, depth, buf, count, None);
+            }
+            Violation::MaxItems => {
+                let count = self.max_items.unwrap() + 1 + rng.next() % config.max_repeat;
+                self.array(rng, config, depth, buf, count, None);
+            }
+            Violation::Property(i) => self.object(rng, config, depth, buf, None, Some(i)),
+            Violation::Items => {
+                let count = self.item_count(rng, config).max(1);
+                let invalid = rng.next() % count;
+                self.array(rng, config, depth, buf, count, Some(invalid));
+            }
+            Violation::OneOf => {
+                // Breaking the chosen branch leaves no branch matching
+                let branch = &self.one_of[rng.next() % self.one_of.len()];
+                branch.invalid_instance(rng, config, depth, buf);
+            }
+        }
+    }
+
+    /// Number in `minimum..=maximum`
+    fn number(&self, rng: &mut Rng, integer: bool, buf: &mut Vec<u8>) {
+        let (mut lo, mut hi) = match (self.minimum, self.maximum) {
+            (Some(min), Some(max)) => (min, max),
+            (Some(min), None) => (min, min + 1000.),
+            (None, Some(max)) => (max - 1000., max),
+            (None, None) => (-1000., 1000.),
+        };
+
+        if integer {
+            lo = lo.ceil();
+            hi = hi.floor();
+            let span = (hi - lo).min(u32::MAX as f64) as usize;
+            return write_number(lo + (rng.next() % (span + 1)) as f64, buf);
+        }
+
+        // Blend the bounds so that huge ranges don't overflow to infinity
+        let t = (rng.next() >> 11) as f64 / (1u64 << 53) as f64;
+        write_number(lo * (1. - t) + hi * t, buf);
+    }
+
+    /// String within the length bounds, matching `pattern` if present
+    fn string(&self, rng: &mut Rng, config: &Config, buf: &mut Vec<u8>) {
+        if let Some(pattern) = &self.pattern {
+            let mut s = String::new();
+            pattern.generate(rng, config, &mut s);
+            return write_string(&s, buf);
+        }
+
+        let max = self
+            .max_length
+            .unwrap_or(self.min_length + config.max_repeat);
+        let len = self.min_length + rng.next() % (max.saturating_sub(self.min_length) + 1);
+        write_string(&self.random_string(rng, len), buf);
+    }
+
+    /// `len` characters, mostly printable ASCII with some that need escaping
+    fn random_string(&self, rng: &mut Rng, len: usize) -> String {
+        let mut s = String::new();
+        while s.chars().count() < len {
+            let c = match rng.next() % 16 {
+                0 => (rng.next() % 0x20) as u8 as char,
+                1 => ['"', '\\', '/'][rng.next() % 3],
+                2 => match core::char::from_u32((rng.next() % 0x10ffff) as u32) {
+                    Some(c) => c,
+                    None => continue,
+                },
+                _ => (0x20 + rng.next() % 0x5f) as u8 as char,
+            };
+            s.push(c);
+        }
+        s
+    }
+
+    /// Item count within `minItems..=maxItems`
+    fn item_count(&self, rng: &mut Rng, config: &Config) -> usize {
+        let max = self.max_items.unwrap_or(self.min_items + config.max_repeat);
+        self.min_items + rng.next() % (max.saturating_sub(self.min_items) + 1)
+    }
+
+    /// Array of `count` items, the one at index `invalid` breaking `items`
+    fn array(
+        &self,
+        rng: &mut Rng,
+        config: &Config,
+        depth: &mut u64,
+        buf: &mut Vec<u8>,
+        count: usize,
+        invalid: Option<usize>,
+    ) {
+        buf.push(b'[');
+        for i in 0..count {
+            if i > 0 {
+                buf.push(b',');
+            }
+            match &self.items {
+                Some(items) if invalid == Some(i) => {
+                    items.invalid_instance(rng, config, depth, buf)
+                }
+                Some(items) => items.instance(rng, config, depth, buf),
+                None => JsonValue::generate(rng, config, depth, buf),
+            }
+        }
+        buf.push(b']');
+    }
+
+    /// Object with every required property except the one at index
+    /// `missing`, plus a random subset of the optional ones. The property at
+    /// index `invalid` is always present and breaks its schema.
+    fn object(
+        &self,
+        rng: &mut Rng,
+        config: &Config,
+        depth: &mut u64,
+        buf: &mut Vec<u8>,
+        missing: Option<usize>,
+        invalid: Option<usize>,
+    ) {
+        buf.push(b'{');
+        let mut first = true;
+        for (i, name) in self.required.iter().enumerate() {
+            if missing == Some(i) || self.properties.iter().any(|(k, _)| k == name) {
+                continue;
+            }
+
+            // Required without a property schema, anything goes
+            if !first {
+                buf.push(b',');
+            }
+            first = false;
+            write_string(name, buf);
+            buf.push(b':');
+            JsonValue::generate(rng, config, depth, buf);
+        }
+        for (i, (name, property)) in self.properties.iter().enumerate() {
+            let required = self.required.iter().position(|r| r == name);
+            let include = match required {
+                Some(r) => missing != Some(r),
+                None => invalid == Some(i) || rng.next() % 2 == 1,
+            };
+            if !include {
+                continue;
+            }
+
+            if !first {
+                buf.push(b',');
+            }
+            first = false;
+            write_string(name, buf);
+            buf.push(b':');
+            if invalid == Some(i) {
+                property.invalid_instance(rng, config, depth, buf);
+            } else {
+                property.instance(rng, config, depth, buf);
+            }
+        }
+        buf.push(b'}');
+    }
+
+    /// Types with values not allowed by `type`
+    ///
+    /// `Number` stands for non-integral numbers if `Integer` is allowed.
+    fn wrong_types(&self) -> Vec<Type> {
+        [
+            Type::Null,
+            Type::Boolean,
+            Type::Number,
+            Type::String,
+            Type::Array,
+            Type::Object,
+        ]
+        .iter()
+        .copied()
+        .filter(|ty| !self.types.contains(ty))
+        .collect()
+    }
+
+    /// Value of a type not allowed by `type`
+    fn wrong_type(&self, rng: &mut Rng, config: &Config, depth: &mut u64, buf: &mut Vec<u8>) {
+        let allows = |ty| self.types.contains(&ty);
+        let options = self.wrong_types();
+        match options[rng.next() % options.len()] {
+            Type::Null => buf.extend_from_slice(b"null"),
+            Type::Boolean => buf.extend_from_slice(b"false"),
+            Type::String => JsonString::generate(rng, config, depth, buf),
+            Type::Array => JsonArray::generate(rng, config, depth, buf),
+            Type::Object => JsonObject::generate(rng, config, depth, buf),
+            Type::Number if allows(Type::Integer) => {
+                // Integers are allowed, so it has to have a fraction
+                let whole = rng.next() % 1000;
+                let fraction = 1 + rng.next() % 9;
+                buf.extend_from_slice(format!("{}.{}", whole, fraction).as_bytes());
+            }
+            Type::Number => Number::generate(rng, config, depth, buf),
+            Type::Integer => unreachable!(),
+        }
+    }
+}
+
+/// Append an `f64` as a JSON number, with infinities saturated to the
+/// largest finite values as JSON has no way to write them
+fn write_number(num: f64, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(format!("{}", num.clamp(f64::MIN, f64::MAX)).as_bytes());
+}
+
+/// Instance equality as defined by JSON Schema: numbers by value, object
+/// members regardless of order
+fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(_), Value::Number(_)) => a.as_f64() == b.as_f64(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_eq(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.iter().any(|(other, b)| key == other && json_eq(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+/// Regular expression subset used by `pattern`
+///
+/// Literals, `.`, classes (`[a-z]`, `[^"]`, `\d`, `\w`, `\s` and their
+/// negations), groups, alternation, anchors and the `* + ? {n,m}`
+/// quantifiers. As in JSON Schema the match isn't anchored unless `^`/`$`
+/// say so.
+#[derive(Debug)]
+pub struct Pattern {
+    /// Alternatives of the whole expression
+    alternatives: Vec<Vec<Node>>,
+}
+
+#[derive(Debug)]
+enum Node {
+    /// Any character in one of the inclusive ranges, or outside of all of
+    /// them if negated
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+impl Node {
+    fn accepts(&self, c: char) -> bool {
+        match self {
+            Node::Class(ranges, negated) => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }
+            _ => false,
+        }
+    }
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+/// Largest `{n,m}` bound kept as is. A larger `m` is as good as no bound, as
+/// generation stays below `n + max_repeat` and a match can't repeat more
+/// often than there are characters, but a larger `n` can't be generated in
+/// reasonable time so the pattern is rejected.
+const MAX_BOUND: usize = 1000;
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Pattern, SchemaError> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut pos = 0;
+        let alternatives = Pattern::alternatives(&chars, &mut pos)
+            .ok_or_else(|| SchemaError::Pattern(pattern.to_string()))?;
+        if pos != chars.len() {
+            return Err(SchemaError::Pattern(pattern.to_string()));
+        }
+        Ok(Pattern { alternatives })
+    }
+
+    fn alternatives(chars: &[char], pos: &mut usize) -> Option<Vec<Vec<Node>>> {
+        let mut alternatives = vec![Vec::new()];
+        while *pos < chars.len() {
+            let atom = match chars[*pos] {
+                ')' => break,
+                '|' => {
+                    *pos += 1;
+                    alternatives.push(Vec::new());
+                    continue;
+                }
+                '(' => {
+                    *pos += 1;
+                    if chars[*pos..].starts_with(&['?', ':']) {
+                        *pos += 2;
+                    }
+                    let group = Pattern::alternatives(chars, pos)?;
+                    if chars.get(*pos) != Some(&')') {
+                        return None;
+                    }
+                    *pos += 1;
+                    Node::Group(group)
+                }
+                '[' => {
+                    *pos += 1;
+                    Pattern::class(chars, pos)?
+                }
+                '.' => {
+                    *pos += 1;
+                    Node::Class(vec![('\n', '\n'), ('\r', '\r')], true)
+                }
+                '^' => {
+                    *pos += 1;
+                    Node::Start
+                }
+                '$' => {
+                    *pos += 1;
+                    Node::End
+                }
+                '\\' => {
+                    *pos += 2;
+                    let (ranges, negated) = Pattern::escape(*chars.get(*pos - 1)?);
+                    Node::Class(ranges, negated)
+                }
+                '*' | '+' | '?' | '{' => return None,
+                c => {
+                    *pos += 1;
+                    Node::Class(vec![(c, c)], false)
+                }
+            };
+
+            let atom = match Pattern::quantifier(chars, pos) {
+                Some((min, max)) => Node::Repeat(Box::new(atom), min, max),
+                None => atom,
+            };
+            alternatives.last_mut().unwrap().push(atom);
+        }
+        Some(alternatives)
+    }
+
+    /// Ranges for the character after a `\`
+    fn escape(c: char) -> (Vec<(char, char)>, bool) {
+        match c {
+            'd' => (DIGIT.to_vec(), false),
+            'D' => (DIGIT.to_vec(), true),
+            'w' => (WORD.to_vec(), false),
+            'W' => (WORD.to_vec(), true),
+            's' => (SPACE.to_vec(), false),
+            'S' => (SPACE.to_vec(), true),
+            'n' => (vec![('\n', '\n')], false),
+            'r' => (vec![('\r', '\r')], false),
+            't' => (vec![('\t', '\t')], false),
+            c => (vec![(c, c)], false),
+        }
+    }
+
+    /// Body of a `[...]` class, starting after the `[`
+    fn class(chars: &[char], pos: &mut usize) -> Option<Node> {
+        let negated = chars.get(*pos) == Some(&'^');
+        if negated {
+            *pos += 1;
+        }
+
+        let mut ranges = Vec::new();
+        loop {
+            let lo = match *chars.get(*pos)? {
+                ']' => {
+                    *pos += 1;
+
+                    // `[]` matches nothing, unlike `[^]`
+                    if ranges.is_empty() && !negated {
+                        return None;
+                    }
+                    return Some(Node::Class(ranges, negated));
+                }
+                '\\' => {
+                    *pos += 2;
+                    let (escaped, inner_negated) = Pattern::escape(*chars.get(*pos - 1)?);
+                    if inner_negated {
+                        return None;
+                    }
+                    if escaped.len() > 1 || escaped[0].0 != escaped[0].1 {
+                        ranges.extend(escaped);
+                        continue;
+                    }
+                    escaped[0].0
+                }
+                c => {
+                    *pos += 1;
+                    c
+                }
+            };
+
+            if chars.get(*pos) == Some(&'-') && chars.get(*pos + 1).is_some_and(|&c| c != ']') {
+                let hi = chars[*pos + 1];
+                *pos += 2;
+                if hi < lo {
+                    return None;
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+    }
+
+    /// Parse an optional quantifier into its bounds
+    fn quantifier(chars: &[char], pos: &mut usize) -> Option<(usize, Option<usize>)> {
+        let bounds = match chars.get(*pos)? {
+            '*' => (0, None),
+            '+' => (1, None),
+            '?' => (0, Some(1)),
+            '{' => {
+                let close = chars[*pos..].iter().position(|&c| c == '}')? + *pos;
+                let body: String = chars[*pos + 1..close].iter().collect();
+                let mut parts = body.splitn(2, ',');
+                let min = parts.next()?.parse().ok()?;
+                let max = match parts.next() {
+                    None => Some(min),
+                    Some("") => None,
+                    Some(max) => Some(max.parse().ok()?),
+                };
+                if min > MAX_BOUND || max.is_some_and(|max| max < min) {
+                    return None;
+                }
+                *pos = close;
+                (min, max.filter(|&max| max <= MAX_BOUND))
+            }
+            _ => return None,
+        };
+        *pos += 1;
+
+        // Lazy quantifiers match the same strings
+        if chars.get(*pos) == Some(&'?') {
+            *pos += 1;
+        }
+        Some(bounds)
+    }
+
+    /// Whether the pattern matches anywhere in `s`
+    pub fn is_match(&self, s: &str) -> bool {
+        let text: Vec<char> = s.chars().collect();
+        (0..=text.len()).any(|start| {
+            self.alternatives
+                .iter()
+                .any(|alt| match_seq(alt, &text, start, &mut |_| true))
+        })
+    }
+
+    /// Append a string matched by the pattern to `out`, with unbounded
+    /// quantifiers repeated less than `config.max_repeat` more times than
+    /// their minimum
+    pub fn generate(&self, rng: &mut Rng, config: &Config, out: &mut String) {
+        let alt = &self.alternatives[rng.next() % self.alternatives.len()];
+        for node in alt {
+            generate_node(node, rng, config, out);
+        }
+    }
+}
+
+fn generate_node(node: &Node, rng: &mut Rng, config: &Config, out: &mut String) {
+    match node {
+        Node::Start | Node::End => {}
+        Node::Class(ranges, false) => {
+            let (lo, hi) = ranges[rng.next() % ranges.len()];
+            let span = hi as usize - lo as usize + 1;
+            let c = core::char::from_u32((lo as usize + rng.next() % span) as u32);
+            out.push(c.unwrap_or(lo));
+        }
+        Node::Class(_, true) => {
+            // Printable ASCII is enough to satisfy most negated classes
+            for _ in 0..64 {
+                let c = (0x20 + rng.next() % 0x5f) as u8 as char;
+                if node.accepts(c) {
+                    return out.push(c);
+                }
+            }
+        }
+        Node::Group(alternatives) => {
+            let alt = &alternatives[rng.next() % alternatives.len()];
+            for node in alt {
+                generate_node(node, rng, config, out);
+            }
+        }
+        Node::Repeat(node, min, max) => {
+            let extra = match max {
+                Some(max) => max - min + 1,
+                None => config.max_repeat,
+            };
+            for _ in 0..(min + rng.next() % extra) {
+                generate_node(node, rng, config, out);
+            }
+        }
+    }
+}
+
+/// Backtracking match of `seq` at `pos`, calling `next` with every end
+/// position until it returns `true`
+fn match_seq(seq: &[Node], text: &[char], pos: usize, next: &mut dyn FnMut(usize) -> bool) -> bool {
+    let (node, rest) = match seq.split_first() {
+        Some(split) => split,
+        None => return next(pos),
+    };
+
+    match node {
+        Node::Start => pos == 0 && match_seq(rest, text, pos, next),
+        Node::End => pos == text.len() && match_seq(rest, text, pos, next),
+        Node::Class(..) => {
+            pos < text.len() && node.accepts(text[pos]) && match_seq(rest, text, pos + 1, next)
+        }
+        Node::Group(alternatives) => alternatives
+            .iter()
+            .any(|alt| match_seq(alt, text, pos, &mut |end| match_seq(rest, text, end, next))),
+        Node::Repeat(inner, min, max) => match_repeat(inner, *min, *max, 0, rest, text, pos, next),
+    }
+}
+
+/// Greedy match of `count..` more repetitions of `node` followed by `rest`
+#[allow(clippy::too_many_arguments)]
+fn match_repeat(
+    node: &Node,
+    min: usize,
+    max: Option<usize>,
+    count: usize,
+    rest: &[Node],
+    text: &[char],
+    pos: usize,
+    next: &mut dyn FnMut(usize) -> bool,
+) -> bool {
+    let more = max.is_none_or(|max| count < max)
+        && match_seq(core::slice::from_ref(node), text, pos, &mut |end| {
+            // An empty repetition can be repeated to reach `min` for free
+            if end == pos {
+                match_seq(rest, text, end, next)
+            } else {
+                match_repeat(node, min, max, count + 1, rest, text, end, next)
+            }
+        });
+    more || (count >= min && match_seq(rest, text, pos, next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_quantifiers() {
+        assert!(Pattern::parse("a{2000000000}").is_err());
+        assert!(Pattern::parse(&format!("a{{{}}}", MAX_BOUND + 1)).is_err());
+
+        let config = Config::default();
+        for pattern in [
+            "^a{0,18446744073709551615}$",
+            "^a{3,2000000000}$",
+            "^a{1000}$",
+        ] {
+            let pattern = Pattern::parse(pattern).unwrap();
+            for seed in 0..100 {
+                let mut s = String::new();
+                pattern.generate(&mut Rng::seeded(seed), &config, &mut s);
+                assert!(s.len() <= MAX_BOUND + config.max_repeat, "{}", s.len());
+                assert!(pattern.is_match(&s), "{:?}", s);
+            }
+        }
+    }
+
+    #[test]
+    fn unbounded_quantifiers_follow_max_repeat() {
+        let config = Config {
+            max_repeat: 3,
+            ..Config::default()
+        };
+        let pattern = Pattern::parse("^a{2,}$").unwrap();
+        for seed in 0..100 {
+            let mut s = String::new();
+            pattern.generate(&mut Rng::seeded(seed), &config, &mut s);
+            assert!((2..5).contains(&s.len()), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn huge_bounds_stay_finite() {
+        let config = Config::default();
+        for schema in [
+            r#"{"type": "number", "minimum": 1e308}"#,
+            r#"{"type": "number", "maximum": -1e308}"#,
+            r#"{"type": "number", "minimum": -1e400, "maximum": 1e400}"#,
+        ] {
+            let schema = Schema::parse(schema.as_bytes()).unwrap();
+            for seed in 0..100 {
+                let mut buf = Vec::new();
+                let rng = &mut Rng::seeded(seed);
+                schema.generate(rng, &config, &mut 0, &mut buf);
+                schema.generate_invalid(rng, &config, &mut 0, &mut buf);
+                let s = String::from_utf8(buf).unwrap();
+                assert!(!s.contains("inf") && !s.contains("NaN"), "{}", s);
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Generation server on a Unix domain socket
+//!
+//! Lets harnesses written in any language use the grammars without linking
+//! Rust code. A client sends requests, each a JSON object on its own line,
+//! and gets a response to each in order on the same connection:
+//!
+//! ```text
+//! {"count":3,"rule":"JsonArray","seed":42,"weights":{"null":0}}
+//! ```
+//!
+//! | Field        | Meaning                                              |
+//! |--------------|------------------------------------------------------|
+//! | `count`      | Number of inputs, required, up to 65536              |
+//! | `grammar`    | Grammar name, as `--grammar`                         |
+//! | `rule`       | Start rule, as `--rule`                              |
+//! | `mode`       | `valid`, `invalid`, `oracle` or `float`, as `--mode` |
+//! | `seed`       | Seed, the same request with the same seed always     |
+//! |              | gets the same inputs                                 |
+//! | `weights`    | Object of `JsonValue` weights, as `--weights`        |
+//! | `max_depth`  | As `--max-depth`, up to 65536                        |
+//! | `max_repeat` | As `--max-repeat`, up to 1024                        |
+//! | `keys`       | As `--keys`                                          |
+//!
+//! Fields left out take the value given on the server's command line. The
+//! response is either the line `error MESSAGE\n`, or the line `ok SEED\n`
+//! followed by the inputs, each preceded by its length as a little-endian
+//! `u32`, and the length `0xffffffff` after the last one. Grammars that can
+//! fail to generate, such as unsatisfiable schemas, may return fewer than
+//! `count` inputs.
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::thread;
+
+use crate::cli::{self, Args, Format, Mode};
+use crate::Task;
+use bnf::value::Value;
+use bnf::{clock_seed, input_seed};
+
+/// Length marking the end of a response
+const END: u32 = u32::MAX;
+
+/// Attempts per requested input before giving up on a request
+const ATTEMPTS: u64 = 16;
+
+/// Largest `count` of a request
+const MAX_COUNT: u64 = 1 << 16;
+
+/// Largest `max_depth` of a request, which the rules of every mode recurse
+/// through within `STACK_SIZE`
+const MAX_DEPTH: u64 = 1 << 16;
+
+/// Largest `max_repeat` of a request
+const MAX_REPEAT: u64 = 1 << 10;
+
+/// Stack of the thread serving a client
+const STACK_SIZE: usize = 256 << 20;
+
+/// Accept clients on the socket at `path` forever, serving each on its own
+/// thread. `defaults` holds the command line, used for fields missing from
+/// requests.
+pub fn serve(path: &Path, defaults: &Args) -> io::Result<()> {
+    // Replace the socket of an earlier run, but nothing else
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let defaults = defaults.clone();
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                // The client went away, nothing to report to
+                let _ = handle(stream, &defaults);
+            })?;
+    }
+    Ok(())
+}
+
+fn handle(stream: UnixStream, defaults: &Args) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        match request(&line, defaults) {
+            Ok((args, task, count)) => respond(&mut writer, &args, &task, count)?,
+            Err(msg) => writeln!(writer, "error {}", msg)?,
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Command line equivalent of a request, the task it asks for and the
+/// number of inputs
+fn request(line: &[u8], defaults: &Args) -> Result<(Args, Task, u64), String> {
+    let request = Value::parse(line).map_err(|err| format!("invalid request: {}", err))?;
+    let pairs = match &request {
+        Value::Object(pairs) => pairs,
+        _ => return Err("request isn't an object".to_string()),
+    };
+
+    let mut args = defaults.clone();
+    let mut count = None;
+    for (key, value) in pairs {
+        let string = || {
+            value
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| format!("{} needs a string", key))
+        };
+        let number = || {
+            value
+                .as_u64()
+                .ok_or_else(|| format!("{} needs a non-negative integer", key))
+        };
+        match key.as_str() {
+            "count" => count = Some(number()?),
+            "grammar" => args.grammar = string()?,
+            "rule" => args.rule = string()?,
+            "mode" => {
+                let mode = string()?;
+                args.mode =
+                    Mode::from_name(&mode).ok_or_else(|| format!("unknown mode {}", mode))?;
+            }
+            "seed" => args.seed = Some(number()?),
+            "max_depth" => args.config.max_depth = number()?,
+            "max_repeat" => {
+                args.config.max_repeat = number()? as usize;
+                if args.config.max_repeat == 0 {
+                    return Err("max_repeat needs to be at least 1".to_string());
+                }
+            }
+            "keys" => args.config.key_mode = cli::key_mode(&string()?)?,
+            "weights" => {
+                let weights = match value {
+                    Value::Object(weights) => weights,
+                    _ => return Err("weights needs an object".to_string()),
+                };
+                for (alt, weight) in weights {
+                    let weight = weight
+                        .as_u64()
+                        .filter(|&weight| weight <= u32::MAX as u64)
+                        .ok_or_else(|| format!("invalid weight for {}", alt))?;
+                    cli::set_weight(&mut args.config, alt, weight as u32)?;
+                }
+                cli::check_weights(&args.config)?;
+            }
+            _ => return Err(format!("unknown field {}", key)),
+        }
+    }
+
+    let count = count.ok_or("count is missing")?;
+
+    // Also applies to the defaults, a client can't make the server overflow
+    // its stack or spin forever
+    let limits = [
+        ("count", count, MAX_COUNT),
+        ("max_depth", args.config.max_depth, MAX_DEPTH),
+        ("max_repeat", args.config.max_repeat as u64, MAX_REPEAT),
+    ];
+    for (name, num, max) in limits {
+        if num > max {
+            return Err(format!("{} can't be more than {}", name, max));
+        }
+    }
+    let task = Task::new(&args)?;
+    Ok((args, task, count))
+}
+
+fn respond(out: &mut impl Write, args: &Args, task: &Task, count: u64) -> io::Result<()> {
+    let seed = args.seed.unwrap_or_else(clock_seed);
+    writeln!(out, "ok {}", seed)?;
+
+    // Same seeds as a run of the binary, so `bnf --replay` works on them
+    let mut buf = Vec::new();
+    let mut generated = 0;
+    let mut sequence = 0;
+    while generated < count && sequence < count.saturating_mul(ATTEMPTS) {
+        buf.clear();
+        if task
+            .run(input_seed(seed, sequence), &args.config, &mut buf)
+            .is_some()
+        {
+            Format::Length.write(out, &buf)?;
+            generated += 1;
+        }
+        sequence += 1;
+    }
+    out.write_all(&END.to_le_bytes())
+}
@@ -0,0 +1,155 @@
+//! Destinations of generated bytes
+//!
+//! The rules of `json4` write through `Sink`, so the same rules fill a `Vec`,
+//! stream to an `io::Write`, or count or hash what they generate without
+//! keeping it:
+//!
+//! ```ignore
+//! let mut out = Stream::new(BufWriter::new(File::create("huge.json")?));
+//! Json::generate(&mut rng, &config, &mut 0, &mut out);
+//! out.finish()?.flush()?;
+//! ```
+use alloc::vec::Vec;
+use core::hash::Hasher;
+
+/// Destination of the bytes of a rule
+pub trait Sink {
+    /// Append `byte`
+    fn push(&mut self, byte: u8);
+
+    /// Append `bytes`
+    fn extend_from_slice(&mut self, bytes: &[u8]);
+}
+
+impl Sink for Vec<u8> {
+    fn push(&mut self, byte: u8) {
+        Vec::push(self, byte);
+    }
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        Vec::extend_from_slice(self, bytes);
+    }
+}
+
+/// Counts the bytes without keeping them
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Count(pub u64);
+
+impl Sink for Count {
+    fn push(&mut self, _byte: u8) {
+        self.0 += 1;
+    }
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len() as u64;
+    }
+}
+
+/// Feeds the bytes to a hasher without keeping them
+///
+/// Rules write a byte or a few at a time, so the hash only matches the one of
+/// the whole output with hashers that don't depend on how it is split, such
+/// as `DefaultHasher`.
+#[derive(Debug, Default, Clone)]
+pub struct Hash<H>(pub H);
+
+impl<H: Hasher> Sink for Hash<H> {
+    fn push(&mut self, byte: u8) {
+        self.0.write_u8(byte);
+    }
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.0.write(bytes);
+    }
+}
+
+/// Fills a buffer, dropping what doesn't fit but still counting it, like
+/// `snprintf`
+///
+/// The output is cut short wherever the buffer ends. `fixed::generate` keeps
+/// it valid instead.
+#[derive(Debug)]
+pub struct Slice<'a> {
+    buf: &'a mut [u8],
+
+    /// Bytes generated, including the dropped ones
+    len: usize,
+}
+
+impl<'a> Slice<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Slice<'a> {
+        Slice { buf, len: 0 }
+    }
+
+    /// Bytes generated, which were cut short if more than the buffer holds
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes in the buffer
+    pub fn written(&self) -> &[u8] {
+        &self.buf[..self.len.min(self.buf.len())]
+    }
+}
+
+impl Sink for Slice<'_> {
+    fn push(&mut self, byte: u8) {
+        if let Some(slot) = self.buf.get_mut(self.len) {
+            *slot = byte;
+        }
+        self.len += 1;
+    }
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        let start = self.len.min(self.buf.len());
+        let end = (self.len + bytes.len()).min(self.buf.len());
+        self.buf[start..end].copy_from_slice(&bytes[..end - start]);
+        self.len += bytes.len();
+    }
+}
+
+/// Streams the bytes to an `io::Write`, for documents too large to keep
+///
+/// Rules can't fail, so the first error is kept for `finish` and everything
+/// after it is dropped. Every byte is a call to `write_all`, a `BufWriter`
+/// makes that cheap.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Stream<W> {
+    inner: W,
+    error: Option<std::io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Stream<W> {
+    pub fn new(inner: W) -> Stream<W> {
+        Stream { inner, error: None }
+    }
+
+    /// The writer, or the first error writing to it
+    pub fn finish(self) -> std::io::Result<W> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.inner),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Sink for Stream<W> {
+    fn push(&mut self, byte: u8) {
+        self.extend_from_slice(&[byte]);
+    }
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        if self.error.is_none() {
+            if let Err(err) = self.inner.write_all(bytes) {
+                self.error = Some(err);
+            }
+        }
+    }
+}
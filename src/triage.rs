@@ -0,0 +1,308 @@
+//! Crash deduplication
+//!
+//! A long run tends to hit the same bug over and over. Crashes are put in
+//! buckets by their kind, such as the signal or the sanitizer's bug type,
+//! and the innermost frames of their stack trace, taken from the sanitizer
+//! report of a program or the backtrace of a panic. Only the smallest input
+//! of each bucket is kept, named `id:000000,bucket:HASH,seed:SEED`.
+//!
+//! Crashes without a stack trace, such as a plain segmentation fault of an
+//! uninstrumented program, are only told apart by their kind.
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::corpus::{Corpus, Meta};
+
+/// Stack frames making up a bucket
+pub const FRAMES: usize = 3;
+
+/// Crash of a target on an input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crash {
+    /// What went wrong, such as `SIGSEGV`, `SIGABRT heap-buffer-overflow` or
+    /// `panic`
+    pub kind: String,
+
+    /// Innermost frames of the stack trace, up to `FRAMES`, innermost first
+    pub frames: Vec<String>,
+
+    /// Description for the user
+    pub message: String,
+}
+
+impl Crash {
+    /// Crash of a program whose standard error was `stderr`
+    pub fn from_report(kind: String, message: String, stderr: &[u8]) -> Crash {
+        let stderr = String::from_utf8_lossy(stderr);
+        let kind = match sanitizer_bug(&stderr) {
+            Some(bug) => format!("{} {}", kind, bug),
+            None => kind,
+        };
+        Crash {
+            kind,
+            frames: sanitizer_frames(&stderr),
+            message,
+        }
+    }
+
+    /// Crash of a panic at `location` with `backtrace`
+    pub fn from_panic(message: String, location: Option<String>, backtrace: &Backtrace) -> Crash {
+        // The panic site tells assertions in the same function apart
+        let mut frames: Vec<String> = location.into_iter().collect();
+        frames.extend(panic_frames(&backtrace.to_string()));
+        Crash {
+            kind: "panic".to_string(),
+            frames,
+            message,
+        }
+    }
+
+    /// Hash identifying the bucket of the crash
+    ///
+    /// FNV-1a, which unlike `DefaultHasher` is the same in every build, so
+    /// later runs keep adding to the buckets of earlier ones.
+    pub fn bucket(&self) -> u64 {
+        let mut hash = 0xcbf29ce484222325u64;
+        for part in core::iter::once(&self.kind).chain(&self.frames) {
+            for &byte in part.as_bytes().iter().chain(&[0]) {
+                hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+}
+
+/// Bug type from the first line of a sanitizer report, such as
+/// `heap-buffer-overflow` in
+/// `==1==ERROR: AddressSanitizer: heap-buffer-overflow on address ...`
+fn sanitizer_bug(stderr: &str) -> Option<&str> {
+    for line in stderr.lines() {
+        if let Some((_, rest)) = line.split_once("ERROR: ") {
+            if let Some((sanitizer, rest)) = rest.split_once(": ") {
+                if sanitizer.ends_with("Sanitizer") {
+                    return rest.split_whitespace().next();
+                }
+            }
+        }
+        if line.contains(": runtime error: ") {
+            return Some("runtime-error");
+        }
+    }
+    None
+}
+
+/// Innermost frames of the first stack trace of a sanitizer report, lines
+/// like `    #0 0x4f5a3e in parse_value /src/json.c:42:7` or
+/// `    #0 0x4f5a3e  (/out/json+0x4f5a3e)` without symbols
+fn sanitizer_frames(stderr: &str) -> Vec<String> {
+    let mut frames = Vec::new();
+    for line in stderr.lines() {
+        let line = line.trim();
+        let frame = match line.strip_prefix('#') {
+            Some(frame) if frame.starts_with(|c: char| c.is_ascii_digit()) => frame,
+            // The first trace ended, the others tell where memory came from
+            _ if !frames.is_empty() => break,
+            _ => continue,
+        };
+        let function = match frame.split_once(" in ") {
+            Some((_, rest)) => rest.split_whitespace().next(),
+            None => frame.split_whitespace().last(),
+        };
+        match function {
+            Some(function) if !is_runtime_frame(function) => frames.push(function.to_string()),
+            _ => {}
+        }
+        if frames.len() == FRAMES {
+            break;
+        }
+    }
+    frames
+}
+
+/// Whether a sanitizer frame is part of the runtime rather than the program
+fn is_runtime_frame(function: &str) -> bool {
+    const PREFIXES: [&str; 7] = [
+        "__asan",
+        "__ubsan",
+        "__msan",
+        "__sanitizer",
+        "__interceptor",
+        "___interceptor",
+        "__GI_",
+    ];
+    PREFIXES.iter().any(|prefix| function.starts_with(prefix))
+        || ["abort", "raise", "__assert_fail"].contains(&function)
+}
+
+/// Innermost frames of a `std::backtrace::Backtrace` below the panic
+/// machinery, lines like `   7: json::parse_value`
+///
+/// The backtrace is captured in the panic hook, so it starts with the hook
+/// and the panic machinery, which are skipped, and ends with the harness
+/// calling the target, where the frames stop.
+fn panic_frames(backtrace: &str) -> Vec<String> {
+    const RUNTIME: [&str; 8] = [
+        "std::",
+        "core::",
+        "alloc::",
+        "<std::",
+        "<core::",
+        "<alloc::",
+        "rust_begin_unwind",
+        "__rust",
+    ];
+    let functions: Vec<&str> = backtrace
+        .lines()
+        .filter_map(|line| match line.trim().split_once(": ") {
+            Some((index, function)) if index.parse::<u32>().is_ok() => Some(function),
+            _ => None,
+        })
+        .collect();
+
+    // The capture and the hook come before the panic machinery, which ends
+    // with the runtime frames skipped below
+    let start = functions
+        .iter()
+        .position(|function| function.contains("::panicking::"))
+        .map_or(0, |pos| pos + 1);
+
+    let mut frames = Vec::new();
+    for function in &functions[start..] {
+        if RUNTIME.iter().any(|prefix| function.starts_with(prefix)) {
+            continue;
+        }
+        // The harness calling the target is the same for every crash
+        if function.contains("::harness::") {
+            break;
+        }
+        frames.push(function.to_string());
+        if frames.len() == FRAMES {
+            break;
+        }
+    }
+    frames
+}
+
+/// Crash directory keeping the smallest input of each bucket
+pub struct Buckets {
+    corpus: Corpus,
+
+    /// File name and size of the input kept for each bucket
+    kept: HashMap<u64, (String, u64)>,
+}
+
+impl Buckets {
+    /// Open `dir`, creating it if needed, and pick up the buckets of
+    /// earlier runs
+    pub fn open(dir: &Path) -> io::Result<Buckets> {
+        let corpus = Corpus::open(dir, true)?;
+        let mut kept = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            let bucket = name
+                .split(',')
+                .find_map(|tag| tag.strip_prefix("bucket:"))
+                .and_then(|hash| u64::from_str_radix(hash, 16).ok());
+            if let Some(bucket) = bucket {
+                kept.insert(bucket, (name, entry.metadata()?.len()));
+            }
+        }
+        Ok(Buckets { corpus, kept })
+    }
+
+    /// Keep `input` if it is the first or the smallest of the bucket of
+    /// `crash`, returning whether the bucket is new
+    pub fn save(&mut self, crash: &Crash, input: &[u8], meta: &Meta) -> io::Result<bool> {
+        let bucket = crash.bucket();
+        let new = match self.kept.get(&bucket) {
+            Some((_, size)) if *size <= input.len() as u64 => return Ok(false),
+            Some((old, _)) => {
+                self.corpus.remove(old)?;
+                false
+            }
+            None => true,
+        };
+        let tags = format!("bucket:{:016x},", bucket);
+        let name = self.corpus.save_tagged(input, meta, &tags)?;
+        self.kept.insert(bucket, (name, input.len() as u64));
+        Ok(new)
+    }
+
+    /// Number of buckets
+    pub fn buckets(&self) -> usize {
+        self.kept.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Backtrace captured in the hook of `Harness::run` for a panic in
+    /// `json::parse_value` called from `json::parse`
+    const BACKTRACE: &str = "\
+   0: bnf::harness::Harness::run::{{closure}}
+   1: <alloc::boxed::Box<F,A> as core::ops::function::Fn<Args>>::call
+   2: std::panicking::panic_with_hook
+   3: std::panicking::panic_handler::{closure#0}
+   4: std::sys::backtrace::__rust_end_short_backtrace
+   5: __rustc::rust_begin_unwind
+   6: core::panicking::panic_fmt
+   7: core::panicking::panic
+   8: core::option::unwrap_failed
+   9: core::option::Option<T>::unwrap
+  10: json::parse_value
+             at ./src/json.rs:42:13
+  11: json::parse
+  12: fuzz::main::{{closure}}
+  13: bnf::harness::Harness::fuzz::{{closure}}::{{closure}}
+  14: std::panicking::catch_unwind::do_call
+  15: __rust_try
+  16: bnf::harness::catch
+  17: bnf::harness::Harness::run_loop
+";
+
+    #[test]
+    fn panic_frames_skip_hook_and_runtime() {
+        assert_eq!(
+            panic_frames(BACKTRACE),
+            [
+                "json::parse_value",
+                "json::parse",
+                "fuzz::main::{{closure}}"
+            ]
+        );
+    }
+
+    #[test]
+    fn panic_frames_stop_at_harness() {
+        let backtrace = BACKTRACE.replace("  12: fuzz::main::{{closure}}\n", "");
+        assert_eq!(
+            panic_frames(&backtrace),
+            ["json::parse_value", "json::parse"]
+        );
+    }
+
+    #[test]
+    fn callers_split_buckets() {
+        let crash = |caller: &str| {
+            let backtrace = BACKTRACE.replace("json::parse\n", &format!("{}\n", caller));
+            Crash {
+                kind: "panic".to_string(),
+                frames: panic_frames(&backtrace),
+                message: String::new(),
+            }
+        };
+        assert_ne!(
+            crash("json::parse").bucket(),
+            crash("json::parse_array").bucket()
+        );
+    }
+}
@@ -0,0 +1,420 @@
+//! Minimal JSON document model
+//!
+//! Used to read JSON inputs such as schema documents, to check generated
+//! instances against them and as the expected result of the semantic oracle.
+//! Numbers are kept as exact decimals so that no precision is lost.
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::decimal::Decimal;
+
+/// Parsed JSON value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(Decimal),
+    String(String),
+    Array(Vec<Value>),
+
+    /// Pairs in document order, duplicate keys included
+    Object(Vec<(String, Value)>),
+}
+
+/// Error returned when a document isn't valid JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset in the input where parsing stopped
+    pub offset: usize,
+
+    /// What was expected at `offset`
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.reason, self.offset)
+    }
+}
+
+impl Value {
+    /// Parse a complete JSON text (RFC 8259)
+    ///
+    /// Lone surrogates in `\u` escapes are decoded as U+FFFD since they can't
+    /// be stored in a `String`.
+    pub fn parse(input: &[u8]) -> Result<Value, ParseError> {
+        let mut parser = Parser { input, pos: 0 };
+        parser.whitespace();
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos != input.len() {
+            return Err(parser.error("trailing data"));
+        }
+        Ok(value)
+    }
+
+    /// Value of the first pair named `key` if this is an object
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Nearest `f64` to the number
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(num) => Some(num.to_f64()),
+            _ => None,
+        }
+    }
+
+    /// Number as a `u64` if it is an integer in range, without going
+    /// through `f64`
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(num) => num.to_u64(),
+            _ => None,
+        }
+    }
+
+    /// Number as a `usize` if it is a non-negative integer
+    pub fn as_usize(&self) -> Option<usize> {
+        let num = self.as_f64()?;
+        // `f64::fract` needs std
+        if num >= 0. && num <= usize::MAX as f64 && num as usize as f64 == num {
+            Some(num as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Append the compact serialization of this value to `buf`
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Null => buf.extend_from_slice(b"null"),
+            Value::Bool(true) => buf.extend_from_slice(b"true"),
+            Value::Bool(false) => buf.extend_from_slice(b"false"),
+            Value::Number(num) => buf.extend_from_slice(num.to_string().as_bytes()),
+            Value::String(s) => write_string(s, buf),
+            Value::Array(values) => {
+                buf.push(b'[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        buf.push(b',');
+                    }
+                    value.write(buf);
+                }
+                buf.push(b']');
+            }
+            Value::Object(pairs) => {
+                buf.push(b'{');
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        buf.push(b',');
+                    }
+                    write_string(key, buf);
+                    buf.push(b':');
+                    value.write(buf);
+                }
+                buf.push(b'}');
+            }
+        }
+    }
+}
+
+/// Whether the JSON strings `a` and `b`, quotes included, decode to the
+/// same characters, as `"\u0041"` and `"A"` do, without allocating
+///
+/// Strings that aren't valid JSON are never the same.
+pub fn same_string(a: &[u8], b: &[u8]) -> bool {
+    let mut a = Parser { input: a, pos: 0 };
+    let mut b = Parser { input: b, pos: 0 };
+    if a.expect(b'"', "expected '\"'").is_err() || b.expect(b'"', "expected '\"'").is_err() {
+        return false;
+    }
+    loop {
+        match (a.string_char(), b.string_char()) {
+            (Ok(None), Ok(None)) => return a.pos == a.input.len() && b.pos == b.input.len(),
+            (Ok(Some(x)), Ok(Some(y))) if x == y => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Append `s` to `buf` as a JSON string, escaping only what has to be escaped
+pub fn write_string(s: &str, buf: &mut Vec<u8>) {
+    buf.push(b'"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.extend_from_slice(b"\\\""),
+            '\\' => buf.extend_from_slice(b"\\\\"),
+            '\u{0}'..='\u{1f}' => {
+                const HEX: &[u8; 16] = b"0123456789abcdef";
+                buf.extend_from_slice(b"\\u00");
+                buf.push(HEX[c as usize >> 4]);
+                buf.push(HEX[c as usize & 0xf]);
+            }
+            _ => buf.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    buf.push(b'"');
+}
+
+/// Recursive descent parser over the raw input bytes
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, reason: &'static str) -> ParseError {
+        ParseError {
+            offset: self.pos,
+            reason,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8, reason: &'static str) -> Result<(), ParseError> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(reason));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, text: &[u8], value: Value) -> Result<Value, ParseError> {
+        if !self.input[self.pos..].starts_with(text) {
+            return Err(self.error("invalid literal"));
+        }
+        self.pos += text.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some(b'n') => self.literal(b"null", Value::Null),
+            Some(b't') => self.literal(b"true", Value::Bool(true)),
+            Some(b'f') => self.literal(b"false", Value::Bool(false)),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                loop {
+                    self.whitespace();
+                    values.push(self.value()?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(values));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut pairs = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(pairs));
+                }
+                loop {
+                    self.whitespace();
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("expected string key"));
+                    }
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(b':', "expected ':'")?;
+                    self.whitespace();
+                    pairs.push((key, self.value()?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(pairs));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(_) => Err(self.error("expected value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+
+        // '-'? ('0' | [1-9] [0-9]*)
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => {
+                self.digits();
+            }
+            _ => return Err(self.error("expected digit")),
+        }
+
+        // ('.' [0-9]+)?
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if self.digits() == 0 {
+                return Err(self.error("expected fraction digit"));
+            }
+        }
+
+        // ([Ee] [+\-]? [0-9]+)?
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                return Err(self.error("expected exponent digit"));
+            }
+        }
+
+        // The grammar was checked above
+        Ok(Value::Number(
+            Decimal::parse(&self.input[start..self.pos]).unwrap(),
+        ))
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let mut res = 0;
+        for _ in 0..4 {
+            let digit = match self.peek() {
+                Some(c @ b'0'..=b'9') => c - b'0',
+                Some(c @ b'a'..=b'f') => c - b'a' + 10,
+                Some(c @ b'A'..=b'F') => c - b'A' + 10,
+                _ => return Err(self.error("expected hex digit")),
+            };
+            res = res << 4 | digit as u32;
+            self.pos += 1;
+        }
+        Ok(res)
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect(b'"', "expected '\"'")?;
+        let mut res = String::new();
+        while let Some(c) = self.string_char()? {
+            res.push(c);
+        }
+        Ok(res)
+    }
+
+    /// Next character of the string being parsed, `None` past its closing
+    /// quote
+    fn string_char(&mut self) -> Result<Option<char>, ParseError> {
+        let c = match self.peek() {
+            None => return Err(self.error("unterminated string")),
+            Some(b'"') => {
+                self.pos += 1;
+                return Ok(None);
+            }
+            Some(0..=0x1f) => return Err(self.error("control character in string")),
+            Some(b'\\') => {
+                self.pos += 1;
+                let escaped = match self.peek() {
+                    Some(b'"') => '"',
+                    Some(b'\\') => '\\',
+                    Some(b'/') => '/',
+                    Some(b'b') => '\u{8}',
+                    Some(b'f') => '\u{c}',
+                    Some(b'n') => '\n',
+                    Some(b'r') => '\r',
+                    Some(b't') => '\t',
+                    Some(b'u') => {
+                        self.pos += 1;
+                        return self.unicode_escape().map(Some);
+                    }
+                    _ => return Err(self.error("invalid escape")),
+                };
+                self.pos += 1;
+                escaped
+            }
+            Some(first) => {
+                // Plain character, which has to be UTF-8
+                let len = match first {
+                    0..=0x7f => 1,
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    _ => 4,
+                };
+                let end = (self.pos + len).min(self.input.len());
+                match core::str::from_utf8(&self.input[self.pos..end]) {
+                    Ok(run) => {
+                        self.pos = end;
+                        run.chars().next().unwrap()
+                    }
+                    Err(_) => return Err(self.error("invalid UTF-8")),
+                }
+            }
+        };
+        Ok(Some(c))
+    }
+
+    /// Decode the rest of a `\u` escape, joining surrogate pairs
+    fn unicode_escape(&mut self) -> Result<char, ParseError> {
+        let first = self.hex4()?;
+        if (0xd800..0xdc00).contains(&first) && self.input[self.pos..].starts_with(b"\\u") {
+            let save = self.pos;
+            self.pos += 2;
+            let second = self.hex4()?;
+            if (0xdc00..0xe000).contains(&second) {
+                let num = 0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00);
+                return Ok(core::char::from_u32(num).unwrap());
+            }
+            self.pos = save;
+        }
+        Ok(core::char::from_u32(first).unwrap_or('\u{fffd}'))
+    }
+}
@@ -0,0 +1,41 @@
+//! Runs the `bnf` binary on command lines that have to terminate
+
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Run `bnf` with `args`, failing if it hasn't exited successfully within a
+/// few seconds
+fn run(args: &[&str]) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bnf"))
+        .args(args)
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(10) {
+            let _ = child.kill();
+            panic!("bnf {} didn't stop", args.join(" "));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(status.success(), "bnf {} failed", args.join(" "));
+}
+
+#[test]
+fn zero_limits_stop() {
+    run(&["-n", "0", "-s", "1"]);
+    run(&["-b", "0", "-s", "1"]);
+    run(&["-n", "0", "-s", "1", "--dedup"]);
+    run(&["-b", "0", "-s", "1", "-j", "4"]);
+}
+
+#[test]
+fn dedup_stops_at_count() {
+    // Only 22 distinct inputs exist, the last ones come as duplicates
+    run(&["-n", "22", "-s", "1", "--dedup", "-r", "Hex"]);
+}
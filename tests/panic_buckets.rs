@@ -0,0 +1,39 @@
+//! A panic site reached from different callers lands in different buckets
+
+use bnf::harness::Harness;
+
+#[inline(never)]
+fn site(input: &[u8]) {
+    if !input.is_empty() {
+        panic!("boom");
+    }
+}
+
+#[inline(never)]
+fn first_caller(input: &[u8]) {
+    site(input);
+}
+
+#[inline(never)]
+fn second_caller(input: &[u8]) {
+    site(input);
+}
+
+#[test]
+fn callers_split_buckets() {
+    let dir = std::env::temp_dir().join(format!("bnf-panic-buckets-{}", std::process::id()));
+    let harness = Harness {
+        count: Some(200),
+        seed: Some(1),
+        crashes: dir.join("crashes"),
+        ..Harness::default()
+    };
+    let report = harness
+        .fuzz(|input| match input.len() % 2 {
+            0 => first_caller(input),
+            _ => second_caller(input),
+        })
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(report.unique_crashes, 2);
+}